@@ -57,5 +57,5 @@ fn main() {
 
     println!("Result: {:?}", result);
 
-    assert!(result.is_ok());
+    assert_eq!("Bumblebee", result.unwrap().name);
 }
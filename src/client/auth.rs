@@ -0,0 +1,363 @@
+//! Authentication
+//!
+//! Credentials set with `basic_auth`, `bearer_auth` or `digest_auth`, on
+//! `HttperClientBuilder` for every request or on `Get` and `PayloadBuilder`
+//! for a single one, authenticate requests that don't set their own
+//! `Authorization` header.
+//!
+//! Basic and bearer credentials are sent with the request. Digest
+//! credentials answer the `401 Unauthorized` challenge of the server, after
+//! which the request is sent again with the MD5 or SHA-256 digest of the
+//! credentials, preferring SHA-256 when the server offers both. Only the
+//! `auth` quality of protection is supported. Requests with streaming
+//! bodies can't be sent again, so their `401` response is returned.
+//!
+//! Digest challenges are only answered for the origin of the request,
+//! not for the origins it is redirected to.
+//!
+//! # Examples
+//!
+//! ```
+//! use httper::client::HttperClient;
+//!
+//! let httper_client = HttperClient::builder()
+//!     .bearer_auth("token")
+//!     .build()
+//!     .unwrap();
+//!
+//! httper_client
+//!     .get("https://testing.local/protected")
+//!     .digest_auth("user", "password")
+//!     .send();
+//! ```
+
+use client::redirect::{same_origin, SendFuture, SendRequest};
+use client::timeout::from_hyper;
+use failure::Error;
+use futures::future;
+use futures::{Future, Stream};
+use http;
+use hyper::body::Payload;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{self, Method, StatusCode, Uri};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+/// Credentials authenticating requests.
+#[derive(Clone, PartialEq)]
+pub enum Auth {
+    /// HTTP Basic authentication, see RFC 7617.
+    Basic { username: String, password: String },
+    /// A bearer token, see RFC 6750.
+    Bearer(String),
+    /// HTTP Digest authentication, see RFC 7616.
+    Digest { username: String, password: String },
+}
+
+impl Auth {
+    /// Adds the `Authorization` header for basic and bearer
+    /// credentials, unless `headers` already contain one.
+    pub(crate) fn authorize(&self, headers: &mut HeaderMap) -> Result<(), Error> {
+        if headers.contains_key(header::AUTHORIZATION) {
+            return Ok(());
+        }
+
+        let mut authorization = match *self {
+            Auth::Basic {
+                ref username,
+                ref password,
+            } => basic_authorization(username, password)?,
+            Auth::Bearer(ref token) => HeaderValue::from_str(&format!("Bearer {}", token))?,
+            Auth::Digest { .. } => return Ok(()),
+        };
+        authorization.set_sensitive(true);
+        headers.insert(header::AUTHORIZATION, authorization);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Auth::Basic { ref username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"...")
+                .finish(),
+            Auth::Bearer(_) => f.debug_tuple("Bearer").field(&"...").finish(),
+            Auth::Digest { ref username, .. } => f
+                .debug_struct("Digest")
+                .field("username", username)
+                .field("password", &"...")
+                .finish(),
+        }
+    }
+}
+
+/// Builds a `Basic` authorization header value for the given credentials.
+pub(crate) fn basic_authorization(username: &str, password: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(&format!(
+        "Basic {}",
+        base64::encode(&format!("{}:{}", username, password))
+    ))
+    .map_err(Error::from)
+}
+
+/// Sends `request` with `send`, answering a digest challenge from
+/// `origin` with the credentials of `auth`.
+pub(crate) fn digest(
+    request: hyper::Request<hyper::Body>,
+    auth: &Auth,
+    origin: &Uri,
+    send: SendRequest,
+) -> SendFuture {
+    let (username, password) = match *auth {
+        Auth::Digest {
+            ref username,
+            ref password,
+        } => (username.clone(), password.clone()),
+        _ => return send(request),
+    };
+
+    if !same_origin(request.uri(), origin)
+        || request.headers().contains_key(header::AUTHORIZATION)
+        || request.body().content_length().is_none()
+    {
+        return send(request);
+    }
+
+    let (parts, body) = request.into_parts();
+    Box::new(
+        body.concat2()
+            .map_err(from_hyper)
+            .and_then(move |body| {
+                let body = body.to_vec();
+                send(rebuild(&parts, body.clone()))
+                    .map(move |response| (response, parts, body, send))
+            })
+            .and_then(move |(response, parts, body, send)| -> SendFuture {
+                if response.status() != StatusCode::UNAUTHORIZED {
+                    return Box::new(future::ok(response));
+                }
+
+                let uri = parts
+                    .uri
+                    .path_and_query()
+                    .map_or("/", |path_and_query| path_and_query.as_str());
+                let authorization =
+                    Challenge::from_headers(response.headers()).and_then(|challenge| {
+                        challenge.authorization(&parts.method, uri, &username, &password)
+                    });
+
+                match authorization {
+                    Some(mut authorization) => {
+                        authorization.set_sensitive(true);
+                        let mut request = rebuild(&parts, body);
+                        request
+                            .headers_mut()
+                            .insert(header::AUTHORIZATION, authorization);
+                        send(request)
+                    }
+                    None => Box::new(future::ok(response)),
+                }
+            }),
+    )
+}
+
+/// Builds a request from `parts` and `body`, to send it again.
+pub(crate) fn rebuild(parts: &http::request::Parts, body: Vec<u8>) -> hyper::Request<hyper::Body> {
+    let mut request = hyper::Request::new(hyper::Body::from(body));
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn hash(self, data: &str) -> String {
+        match self {
+            Algorithm::Md5 => format!("{:x}", Md5::digest(data.as_bytes())),
+            Algorithm::Sha256 => format!("{:x}", Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+/// A digest challenge from a `WWW-Authenticate` header.
+#[derive(Debug)]
+struct Challenge {
+    algorithm: Algorithm,
+    session: bool,
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    qop: Option<Vec<String>>,
+}
+
+impl Challenge {
+    /// Returns the strongest supported digest challenge in `headers`.
+    fn from_headers(headers: &HeaderMap) -> Option<Challenge> {
+        headers
+            .get_all(header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(Challenge::parse)
+            .max_by_key(|challenge| challenge.algorithm == Algorithm::Sha256)
+    }
+
+    fn parse(value: &str) -> Option<Challenge> {
+        let value = value.trim();
+        match value.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("digest ") => {}
+            _ => return None,
+        }
+
+        let mut params = parse_params(&value[7..]);
+        let algorithm = params
+            .remove("algorithm")
+            .unwrap_or_else(|| "MD5".to_string())
+            .to_uppercase();
+        let (algorithm, session) = match algorithm.as_str() {
+            "MD5" => (Algorithm::Md5, false),
+            "MD5-SESS" => (Algorithm::Md5, true),
+            "SHA-256" => (Algorithm::Sha256, false),
+            "SHA-256-SESS" => (Algorithm::Sha256, true),
+            _ => return None,
+        };
+
+        Some(Challenge {
+            algorithm,
+            session,
+            realm: params.remove("realm")?,
+            nonce: params.remove("nonce")?,
+            opaque: params.remove("opaque"),
+            qop: params.remove("qop").map(|qop| {
+                qop.split(',')
+                    .map(|qop| qop.trim().to_lowercase())
+                    .collect()
+            }),
+        })
+    }
+
+    /// Computes the `Authorization` header answering the challenge,
+    /// or returns `None` if its quality of protection isn't supported.
+    fn authorization(
+        &self,
+        method: &Method,
+        uri: &str,
+        username: &str,
+        password: &str,
+    ) -> Option<HeaderValue> {
+        let qop = match self.qop {
+            Some(ref qop) if qop.iter().any(|qop| qop == "auth") => Some("auth"),
+            Some(_) => return None,
+            None => None,
+        };
+        let cnonce = format!(
+            "{:016x}{:016x}",
+            RandomState::new().build_hasher().finish(),
+            RandomState::new().build_hasher().finish()
+        );
+        let nc = "00000001";
+
+        let mut ha1 = self
+            .algorithm
+            .hash(&format!("{}:{}:{}", username, self.realm, password));
+        if self.session {
+            ha1 = self
+                .algorithm
+                .hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = self.algorithm.hash(&format!("{}:{}", method, uri));
+        let response = match qop {
+            Some(qop) => self.algorithm.hash(&format!(
+                "{}:{}:{}:{}:{}:{}",
+                ha1, self.nonce, nc, cnonce, qop, ha2
+            )),
+            None => self
+                .algorithm
+                .hash(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let algorithm = match (self.algorithm, self.session) {
+            (Algorithm::Md5, false) => "MD5",
+            (Algorithm::Md5, true) => "MD5-sess",
+            (Algorithm::Sha256, false) => "SHA-256",
+            (Algorithm::Sha256, true) => "SHA-256-sess",
+        };
+        let mut authorization = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", \
+             algorithm={}, response=\"{}\"",
+            quote(username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            algorithm,
+            response
+        );
+        if let Some(qop) = qop {
+            authorization.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+        }
+        if let Some(ref opaque) = self.opaque {
+            authorization.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+
+        HeaderValue::from_str(&authorization).ok()
+    }
+}
+
+/// Parses the comma separated `name=value` parameters of a challenge,
+/// where values may be quoted strings.
+fn parse_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut chars = params.chars().peekable();
+
+    loop {
+        while let Some(&c) = chars.peek() {
+            if c == ',' || c.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let name: String = chars
+            .by_ref()
+            .take_while(|&c| c != '=')
+            .collect::<String>()
+            .trim()
+            .to_lowercase();
+        if name.is_empty() {
+            return parsed;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ',').collect();
+        }
+        parsed.insert(name, value.trim().to_string());
+    }
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,240 @@
+//! Request body compression
+//!
+//! A `RequestCompression` set with `HttperClientBuilder::compress`, or per
+//! request with `PayloadBuilder::compress`, compresses request bodies with
+//! gzip or zstd, enabled by the `gzip` and `zstd` features. Bodies of a
+//! known size are compressed before they are sent and keep a known size,
+//! so that they can be sent again on redirects and authentication
+//! challenges. Streaming bodies are compressed while they are sent.
+//! Bodies known to be smaller than the minimum size, 1024 bytes by
+//! default, are sent as is.
+//!
+//! Empty bodies, the bodies of `GET` and `HEAD` requests and requests
+//! setting their own `Content-Encoding` aren't compressed.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "gzip")]
+//! # fn main() {
+//! use httper::client::compress::RequestCompression;
+//! use httper::client::HttperClient;
+//!
+//! let httper_client = HttperClient::new();
+//!
+//! httper_client
+//!     .post("https://testing.local/ingest")
+//!     .payload(vec![b'a'; 4 * 1024 * 1024])
+//!     .compress(RequestCompression::gzip().min_size(64 * 1024))
+//!     .send();
+//! # }
+//! # #[cfg(not(feature = "gzip"))]
+//! # fn main() {}
+//! ```
+
+use failure::Error;
+use futures::future::{self, Either};
+use futures::{Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{self, HeaderValue};
+use hyper::{self, Chunk, Method};
+use std::error::Error as StdError;
+use std::io::{self, Write};
+use std::mem;
+
+/// Decides whether and how request bodies are compressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestCompression {
+    encoding: Option<Encoding>,
+    min_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl RequestCompression {
+    /// Doesn't compress request bodies.
+    pub fn none() -> RequestCompression {
+        RequestCompression {
+            encoding: None,
+            min_size: 1024,
+        }
+    }
+
+    /// Compresses request bodies with gzip.
+    #[cfg(feature = "gzip")]
+    pub fn gzip() -> RequestCompression {
+        RequestCompression {
+            encoding: Some(Encoding::Gzip),
+            ..RequestCompression::none()
+        }
+    }
+
+    /// Compresses request bodies with zstd.
+    #[cfg(feature = "zstd")]
+    pub fn zstd() -> RequestCompression {
+        RequestCompression {
+            encoding: Some(Encoding::Zstd),
+            ..RequestCompression::none()
+        }
+    }
+
+    /// Sets the size in bytes below which bodies aren't compressed.
+    /// Streaming bodies, of unknown size, are always compressed.
+    pub fn min_size(self, min_size: u64) -> RequestCompression {
+        RequestCompression { min_size, ..self }
+    }
+
+    /// Compresses the body of `request` if the policy applies to it,
+    /// setting `Content-Encoding` and the new `Content-Length`.
+    pub(crate) fn compress(
+        &self,
+        request: hyper::Request<hyper::Body>,
+    ) -> impl Future<Item = hyper::Request<hyper::Body>, Error = Error> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => return Either::A(future::ok(request)),
+        };
+
+        let length = request.body().content_length();
+        if request.headers().contains_key(header::CONTENT_ENCODING)
+            || request.method() == Method::GET
+            || request.method() == Method::HEAD
+            || request.body().is_end_stream()
+            || length.map_or(false, |length| length == 0 || length < self.min_size)
+        {
+            return Either::A(future::ok(request));
+        }
+
+        let (mut parts, body) = request.into_parts();
+        let (name, mut encoder) = match encoder(encoding) {
+            Ok(encoder) => encoder,
+            Err(error) => return Either::A(future::err(Error::from(error))),
+        };
+        parts
+            .headers
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static(name));
+        // hyper sets the length of sized bodies itself.
+        parts.headers.remove(header::CONTENT_LENGTH);
+
+        if length.is_none() {
+            let body = CompressedBody {
+                body,
+                encoder: Some(encoder),
+            };
+            return Either::A(future::ok(hyper::Request::from_parts(
+                parts,
+                hyper::Body::wrap_stream(body),
+            )));
+        }
+
+        Either::B(body.concat2().map_err(Error::from).and_then(move |body| {
+            encoder.write_all(&body)?;
+            encoder.finish()?;
+            let encoded = mem::take(encoder.encoded());
+            Ok(hyper::Request::from_parts(
+                parts,
+                hyper::Body::from(encoded),
+            ))
+        }))
+    }
+}
+
+impl Default for RequestCompression {
+    fn default() -> RequestCompression {
+        RequestCompression::none()
+    }
+}
+
+/// An encoder writing the encoded bytes to a buffer.
+trait Encode: Write + Send {
+    /// Returns the bytes encoded so far.
+    fn encoded(&mut self) -> &mut Vec<u8>;
+
+    /// Encodes the remaining bytes and ends the encoded data.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[cfg(feature = "gzip")]
+impl Encode for flate2::write::GzEncoder<Vec<u8>> {
+    fn encoded(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Encode for zstd::stream::write::Encoder<Vec<u8>> {
+    fn encoded(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.do_finish()
+    }
+}
+
+/// Returns the `Content-Encoding` name and an encoder for `encoding`.
+fn encoder(encoding: Encoding) -> io::Result<(&'static str, Box<dyn Encode>)> {
+    match encoding {
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip => Ok((
+            "gzip",
+            Box::new(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+        )),
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd => Ok((
+            "zstd",
+            Box::new(zstd::stream::write::Encoder::new(Vec::new(), 0)?),
+        )),
+    }
+}
+
+/// A request body compressed while it is sent.
+struct CompressedBody {
+    body: hyper::Body,
+    encoder: Option<Box<dyn Encode>>,
+}
+
+impl Stream for CompressedBody {
+    type Item = Chunk;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let encoder = match self.encoder {
+                Some(ref mut encoder) => encoder,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let encoded = match self.body.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    encoder.write_all(&chunk)?;
+                    mem::take(encoder.encoded())
+                }
+                Async::Ready(None) => {
+                    encoder.finish()?;
+                    let encoded = mem::take(encoder.encoded());
+                    self.encoder = None;
+                    encoded
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+
+            if !encoded.is_empty() {
+                return Ok(Async::Ready(Some(Chunk::from(encoded))));
+            }
+        }
+    }
+}
@@ -0,0 +1,618 @@
+use client::dns::DnsResolver;
+use client::proxy::{Proxy, ProxyScheme};
+use client::socks::{self, Address};
+use client::tls::{TlsConnector, TlsStream};
+use failure::{self, Error};
+use futures::{future, Async, Future, Poll};
+use hyper::client::connect::dns::Resolve;
+use hyper::client::connect::{Connect, Connected, Destination};
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio_uds::UnixStream;
+
+/// A connector that performs a TLS handshake, using the TLS backend
+/// selected by the cargo features, on top of the connections made
+/// by the wrapped connector for `https` destinations.
+#[derive(Clone)]
+pub struct HttpsConnector<C> {
+    http: C,
+    tls: TlsConnector,
+}
+
+impl<C> HttpsConnector<C> {
+    pub(crate) fn new(http: C, tls: TlsConnector) -> Self {
+        HttpsConnector { http, tls }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for HttpsConnector<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpsConnector")
+            .field("http", &self.http)
+            .finish()
+    }
+}
+
+impl<C> Connect for HttpsConnector<C>
+where
+    C: Connect,
+    C::Future: 'static,
+    C::Error: 'static,
+{
+    type Transport = MaybeHttpsStream<C::Transport>;
+    type Error = Box<dyn StdError + Send + Sync>;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let is_https = dst.scheme() == "https";
+        let host = dst.host().to_string();
+        let connecting = self.http.connect(dst).map_err(Into::into);
+
+        if !is_https {
+            return Box::new(
+                connecting.map(|(stream, connected)| (MaybeHttpsStream::Http(stream), connected)),
+            );
+        }
+
+        let tls = self.tls.clone();
+        Box::new(connecting.and_then(move |(stream, connected)| {
+            tls.connect(&host, stream).map(|stream| {
+                let connected = if TlsConnector::negotiated_http2(&stream) {
+                    connected.negotiated_h2()
+                } else {
+                    connected
+                };
+                (MaybeHttpsStream::Https(Box::new(stream)), connected)
+            })
+        }))
+    }
+}
+
+/// A stream that is either plain or TLS encrypted.
+pub enum MaybeHttpsStream<T> {
+    Http(T),
+    Https(Box<TlsStream<T>>),
+}
+
+impl<T> fmt::Debug for MaybeHttpsStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MaybeHttpsStream::Http(..) => f.pad("Http(..)"),
+            MaybeHttpsStream::Https(..) => f.pad("Https(..)"),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Read for MaybeHttpsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            MaybeHttpsStream::Http(ref mut stream) => stream.read(buf),
+            MaybeHttpsStream::Https(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Write for MaybeHttpsStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            MaybeHttpsStream::Http(ref mut stream) => stream.write(buf),
+            MaybeHttpsStream::Https(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            MaybeHttpsStream::Http(ref mut stream) => stream.flush(),
+            MaybeHttpsStream::Https(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for MaybeHttpsStream<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match *self {
+            MaybeHttpsStream::Http(ref stream) => stream.prepare_uninitialized_buffer(buf),
+            MaybeHttpsStream::Https(ref stream) => stream.prepare_uninitialized_buffer(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for MaybeHttpsStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            MaybeHttpsStream::Http(ref mut stream) => stream.shutdown(),
+            MaybeHttpsStream::Https(ref mut stream) => stream.shutdown(),
+        }
+    }
+}
+
+/// A connector that routes connections through the first of the
+/// configured proxies intercepting the destination.
+///
+/// Connections to `http` destinations are sent to HTTP proxies directly
+/// while `https` destinations are tunnelled through them with `CONNECT`.
+/// SOCKS5 proxies tunnel connections to any destination.
+#[derive(Debug, Clone)]
+pub struct ProxyConnector<C> {
+    inner: C,
+    proxies: Arc<Vec<Proxy>>,
+    resolver: DnsResolver,
+}
+
+impl<C> ProxyConnector<C> {
+    pub(crate) fn new(inner: C, proxies: Arc<Vec<Proxy>>, resolver: DnsResolver) -> Self {
+        ProxyConnector {
+            inner,
+            proxies,
+            resolver,
+        }
+    }
+}
+
+impl<C> Connect for ProxyConnector<C>
+where
+    C: Connect,
+    C::Future: 'static,
+    C::Error: 'static,
+{
+    type Transport = ProxyStream<C::Transport>;
+    type Error = Box<dyn StdError + Send + Sync>;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let proxy = match self
+            .proxies
+            .iter()
+            .find(|proxy| proxy.intercepts(dst.scheme(), dst.host()))
+        {
+            Some(proxy) => proxy,
+            None => {
+                return Box::new(
+                    self.inner
+                        .connect(dst)
+                        .map(|(stream, connected)| (ProxyStream::new(stream), connected))
+                        .map_err(Into::into),
+                )
+            }
+        };
+
+        let proxy_dst = match Destination::try_from_uri(proxy.uri().clone()) {
+            Ok(proxy_dst) => proxy_dst,
+            Err(error) => return Box::new(future::err(error.into())),
+        };
+        let connecting = self.inner.connect(proxy_dst).map_err(Into::into);
+
+        if proxy.scheme() != ProxyScheme::Http {
+            return self.connect_socks(connecting, proxy, &dst);
+        }
+
+        if dst.scheme() != "https" {
+            return Box::new(
+                connecting
+                    .map(|(stream, connected)| (ProxyStream::new(stream), connected.proxy(true))),
+            );
+        }
+
+        let host = dst.host().to_string();
+        let port = dst.port().unwrap_or(443);
+        let authorization = proxy.authorization().cloned();
+
+        Box::new(connecting.and_then(move |(stream, connected)| {
+            let mut request = format!(
+                "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n",
+                host = host,
+                port = port
+            )
+            .into_bytes();
+            if let Some(authorization) = authorization {
+                request.extend_from_slice(b"Proxy-Authorization: ");
+                request.extend_from_slice(authorization.as_bytes());
+                request.extend_from_slice(b"\r\n");
+            }
+            request.extend_from_slice(b"\r\n");
+
+            Tunnel {
+                stream: Some(stream),
+                request,
+                written: 0,
+                response: Vec::new(),
+            }
+            .map(|stream| (stream, connected))
+        }))
+    }
+}
+
+impl<C> ProxyConnector<C>
+where
+    C: Connect,
+    C::Future: 'static,
+    C::Error: 'static,
+{
+    /// Establishes a tunnel to `dst` through the SOCKS5 proxy
+    /// once `connecting` is connected to it.
+    fn connect_socks<F>(
+        &self,
+        connecting: F,
+        proxy: &Proxy,
+        dst: &Destination,
+    ) -> <Self as Connect>::Future
+    where
+        F: Future<Item = (C::Transport, Connected), Error = Box<dyn StdError + Send + Sync>>
+            + Send
+            + 'static,
+    {
+        let host = dst.host().trim_matches(|c| c == '[' || c == ']');
+        let port = dst
+            .port()
+            .unwrap_or(if dst.scheme() == "https" { 443 } else { 80 });
+        let credentials = proxy.credentials().cloned();
+
+        let address: Box<dyn Future<Item = Address, Error = _> + Send> =
+            match host.parse::<IpAddr>() {
+                Ok(ip) => Box::new(future::ok(Address::Ip(ip))),
+                // Only `socks5` proxies need host names resolved on our side.
+                Err(_) if proxy.scheme() == ProxyScheme::Socks5 => {
+                    let host = host.to_string();
+                    let resolver = self.resolver.clone();
+                    let resolving = future::result(host.parse())
+                        .map_err(|error| Box::new(error) as Box<dyn StdError + Send + Sync>)
+                        .and_then(move |name| resolver.resolve(name).map_err(Into::into))
+                        .and_then(move |mut addrs| {
+                            addrs
+                                .next()
+                                .map(Address::Ip)
+                                .ok_or_else(|| format!("no addresses found for {}", host).into())
+                        });
+                    Box::new(resolving)
+                }
+                Err(_) => Box::new(future::ok(Address::Domain(host.to_string()))),
+            };
+
+        Box::new(
+            connecting
+                .join(address)
+                .and_then(move |((stream, connected), address)| {
+                    socks::connect(stream, address, port, credentials)
+                        .map(|stream| (ProxyStream::new(stream), connected))
+                }),
+        )
+    }
+}
+
+/// A connection made by a `ProxyConnector`, returning the bytes a proxy
+/// sent right after its `CONNECT` response before reading from the
+/// connection itself.
+pub struct ProxyStream<T> {
+    stream: T,
+    leftover: Vec<u8>,
+}
+
+impl<T> ProxyStream<T> {
+    fn new(stream: T) -> Self {
+        ProxyStream {
+            stream,
+            leftover: Vec::new(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for ProxyStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ProxyStream(..)")
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Read for ProxyStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.leftover.is_empty() {
+            return self.stream.read(buf);
+        }
+
+        let read = buf.len().min(self.leftover.len());
+        buf[..read].copy_from_slice(&self.leftover[..read]);
+        self.leftover.drain(..read);
+        Ok(read)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Write for ProxyStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for ProxyStream<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.stream.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for ProxyStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.stream.shutdown()
+    }
+}
+
+/// A future establishing a tunnel through a proxy with `CONNECT`.
+struct Tunnel<S> {
+    stream: Option<S>,
+    request: Vec<u8>,
+    written: usize,
+    response: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite> Future for Tunnel<S> {
+    type Item = ProxyStream<S>;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let stream = self
+                .stream
+                .as_mut()
+                .expect("polled Tunnel after completion");
+
+            while self.written < self.request.len() {
+                match stream.poll_write(&self.request[self.written..])? {
+                    Async::Ready(0) => return Err("proxy closed the connection".into()),
+                    Async::Ready(written) => self.written += written,
+                    Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
+
+            if let Async::NotReady = stream.poll_flush()? {
+                return Ok(Async::NotReady);
+            }
+
+            let mut buf = [0; 1024];
+            while header_end(&self.response).is_none() {
+                match stream.poll_read(&mut buf)? {
+                    Async::Ready(0) => return Err("proxy closed the connection".into()),
+                    Async::Ready(read) => self.response.extend_from_slice(&buf[..read]),
+                    Async::NotReady => return Ok(Async::NotReady),
+                }
+
+                if self.response.len() > 64 * 1024 {
+                    return Err("proxy response headers too large".into());
+                }
+            }
+        }
+
+        // The proxy may already have sent bytes of the tunnelled connection.
+        let end = header_end(&self.response).expect("checked above");
+        let leftover = self.response.split_off(end);
+
+        let status_line = self
+            .response
+            .split(|byte| *byte == b'\n')
+            .next()
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(Async::Ready(ProxyStream {
+                stream: self.stream.take().expect("checked above"),
+                leftover,
+            })),
+            Some("407") => Err("proxy authentication required".into()),
+            _ => Err(format!("proxy tunnel failed: {}", status_line.trim()).into()),
+        }
+    }
+}
+
+/// Returns the length of the response headers in `response`, up to and
+/// including the blank line ending them, if they were fully received.
+fn header_end(response: &[u8]) -> Option<usize> {
+    response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+}
+
+/// A connector that connects to Unix domain sockets for `http+unix`
+/// destinations, or for every destination when a socket path is
+/// configured, and uses the wrapped connector otherwise.
+///
+/// The socket path of `http+unix` destinations is the hex-encoded host,
+/// see `encode_unix_url`.
+#[derive(Debug, Clone)]
+pub struct UnixConnector<C> {
+    inner: C,
+    socket_path: Option<PathBuf>,
+}
+
+impl<C> UnixConnector<C> {
+    pub(crate) fn new(inner: C, socket_path: Option<PathBuf>) -> Self {
+        UnixConnector { inner, socket_path }
+    }
+}
+
+impl<C> Connect for UnixConnector<C>
+where
+    C: Connect,
+    C::Future: 'static,
+    C::Error: 'static,
+{
+    type Transport = MaybeUnixStream<C::Transport>;
+    type Error = Box<dyn StdError + Send + Sync>;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let socket_path = match self.socket_path {
+            Some(ref socket_path) => socket_path.clone(),
+            None if dst.scheme() == UNIX_SCHEME => match hex_decode(dst.host()) {
+                Some(socket_path) => PathBuf::from(socket_path),
+                None => {
+                    return Box::new(future::err(
+                        format!("invalid Unix socket host: {}", dst.host()).into(),
+                    ))
+                }
+            },
+            None => {
+                return Box::new(
+                    self.inner
+                        .connect(dst)
+                        .map(|(stream, connected)| (MaybeUnixStream::Tcp(stream), connected))
+                        .map_err(Into::into),
+                )
+            }
+        };
+
+        connect_unix(socket_path)
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix<T>(
+    socket_path: PathBuf,
+) -> Box<
+    dyn Future<Item = (MaybeUnixStream<T>, Connected), Error = Box<dyn StdError + Send + Sync>>
+        + Send,
+> {
+    Box::new(
+        UnixStream::connect(socket_path)
+            .map(|stream| (MaybeUnixStream::Unix(stream), Connected::new()))
+            .map_err(Into::into),
+    )
+}
+
+#[cfg(not(unix))]
+fn connect_unix<T>(
+    _socket_path: PathBuf,
+) -> Box<
+    dyn Future<Item = (MaybeUnixStream<T>, Connected), Error = Box<dyn StdError + Send + Sync>>
+        + Send,
+> {
+    Box::new(future::err(
+        "Unix domain sockets aren't supported on this platform".into(),
+    ))
+}
+
+const UNIX_SCHEME: &str = "http+unix";
+
+/// Rewrites `http+unix` urls, like `http+unix://%2Fvar%2Frun%2Fdocker.sock/info`,
+/// to carry the socket path hex-encoded as the host, since a `hyper::Uri`
+/// can't have a percent-encoded host. Other urls are returned as is.
+pub(crate) fn encode_unix_url(url: &str) -> Result<Cow<'_, str>, Error> {
+    let prefix = format!("{}://", UNIX_SCHEME);
+    if !url.starts_with(&prefix) {
+        return Ok(Cow::Borrowed(url));
+    }
+
+    let rest = &url[prefix.len()..];
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let socket_path = percent_decode(host)
+        .ok_or_else(|| failure::err_msg(format!("invalid Unix socket path in {}", url)))?;
+
+    let host: String = socket_path
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Ok(Cow::Owned(format!("{}{}{}", prefix, host, path)))
+}
+
+fn percent_decode(input: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut input = input.bytes();
+
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            bytes.push(u8::from_str_radix(::std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    Some(bytes)
+}
+
+fn hex_decode(input: &str) -> Option<String> {
+    if input.is_empty() {
+        return None;
+    }
+
+    let bytes = (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(input.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+/// A stream that is either a TCP or a Unix domain socket connection.
+pub enum MaybeUnixStream<T> {
+    Tcp(T),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl<T> fmt::Debug for MaybeUnixStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MaybeUnixStream::Tcp(..) => f.pad("Tcp(..)"),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix(..) => f.pad("Unix(..)"),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Read for MaybeUnixStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            MaybeUnixStream::Tcp(ref mut stream) => stream.read(buf),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Write for MaybeUnixStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            MaybeUnixStream::Tcp(ref mut stream) => stream.write(buf),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            MaybeUnixStream::Tcp(ref mut stream) => stream.flush(),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for MaybeUnixStream<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match *self {
+            MaybeUnixStream::Tcp(ref stream) => stream.prepare_uninitialized_buffer(buf),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix(ref stream) => stream.prepare_uninitialized_buffer(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for MaybeUnixStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            MaybeUnixStream::Tcp(ref mut stream) => stream.shutdown(),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix(ref mut stream) => AsyncWrite::shutdown(stream),
+        }
+    }
+}
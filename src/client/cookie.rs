@@ -0,0 +1,170 @@
+//! Cookie storage
+//!
+//! A `HttperClient` built with `HttperClientBuilder::cookie_store(true)`, or
+//! given a `CookieJar` with `HttperClientBuilder::cookie_jar`, stores the
+//! cookies set by responses and sends them back with the requests they
+//! apply to, following the domain, path, secure and expiry rules of
+//! RFC 6265.
+//!
+//! Persistent cookies can be saved to a file and loaded again, so
+//! sessions survive restarts.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use httper::client::cookie::CookieJar;
+//! use httper::client::HttperClient;
+//!
+//! let jar = CookieJar::load("cookies.json").unwrap_or_default();
+//!
+//! let httper_client = HttperClient::builder()
+//!     .cookie_jar(jar.clone())
+//!     .build()
+//!     .unwrap();
+//!
+//! // Send requests with `httper_client`, then keep the session.
+//! jar.save("cookies.json").unwrap();
+//! ```
+
+use client::error::Error;
+use cookie_store::CookieStore;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::Uri;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use url::Url;
+
+/// A store of cookies, shared by the clones of the jar.
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    store: Arc<RwLock<CookieStore>>,
+}
+
+impl CookieJar {
+    /// Creates an empty `CookieJar`.
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Loads the cookies saved at `path` with `save`, skipping
+    /// the expired ones.
+    ///
+    /// # Errors
+    /// Will return Err if the file couldn't be read or doesn't
+    /// contain saved cookies.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CookieJar, Error> {
+        let file = BufReader::new(File::open(path).map_err(Error::cookie)?);
+        let store = CookieStore::load_json(file).map_err(Error::cookie_from_failure)?;
+        Ok(CookieJar {
+            store: Arc::new(RwLock::new(store)),
+        })
+    }
+
+    /// Saves the unexpired persistent cookies to `path`. Session
+    /// cookies, set without an expiry, aren't saved.
+    ///
+    /// # Errors
+    /// Will return Err if the file couldn't be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut file = BufWriter::new(File::create(path).map_err(Error::cookie)?);
+        self.read()
+            .save_json(&mut file)
+            .map_err(Error::cookie_from_failure)?;
+        file.flush().map_err(Error::cookie)
+    }
+
+    /// Stores `cookie`, formatted like a `Set-Cookie` header,
+    /// as if it was set by a response from `url`.
+    ///
+    /// # Errors
+    /// Will return Err if `url` isn't a valid url or if the cookie
+    /// is invalid or not allowed to be set by `url`.
+    pub fn add_cookie_str(&self, cookie: &str, url: &str) -> Result<(), Error> {
+        let url = Url::parse(url).map_err(Error::cookie)?;
+        self.write().parse(cookie, &url).map_err(Error::cookie)?;
+        Ok(())
+    }
+
+    /// Returns the value of the `Cookie` header sent to `url`,
+    /// or `None` if no cookie applies.
+    pub fn cookies(&self, url: &str) -> Option<String> {
+        Url::parse(url).ok().and_then(|url| self.header(&url))
+    }
+
+    /// Removes all the cookies.
+    pub fn clear(&self) {
+        self.write().clear();
+    }
+
+    /// Adds the cookies applying to `uri` to the `Cookie` header in `headers`.
+    pub(crate) fn add_request_cookies(&self, uri: &Uri, headers: &mut HeaderMap) {
+        let cookies = match to_url(uri).and_then(|url| self.header(&url)) {
+            Some(cookies) => cookies,
+            None => return,
+        };
+
+        let cookies = match headers.get(header::COOKIE).and_then(|c| c.to_str().ok()) {
+            Some(existing) => format!("{}; {}", existing, cookies),
+            None => cookies,
+        };
+        if let Ok(cookies) = HeaderValue::from_str(&cookies) {
+            headers.insert(header::COOKIE, cookies);
+        }
+    }
+
+    /// Stores the cookies set by the `Set-Cookie` headers
+    /// of a response from `uri`.
+    pub(crate) fn store_response_cookies(&self, uri: &Uri, headers: &HeaderMap) {
+        let url = match to_url(uri) {
+            Some(url) => url,
+            None => return,
+        };
+
+        let mut store = self.write();
+        for cookie in headers.get_all(header::SET_COOKIE) {
+            // Invalid cookies are ignored, as browsers do.
+            if let Ok(cookie) = cookie.to_str() {
+                let _ = store.parse(cookie, &url);
+            }
+        }
+    }
+
+    // A panic while the store was locked can't leave it inconsistent,
+    // so the store keeps being used instead of panicking again.
+    fn read(&self) -> RwLockReadGuard<'_, CookieStore> {
+        self.store.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, CookieStore> {
+        self.store.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn header(&self, url: &Url) -> Option<String> {
+        let store = self.read();
+        let cookies = store
+            .get_request_cookies(url)
+            .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+            .collect::<Vec<_>>();
+
+        if cookies.is_empty() {
+            None
+        } else {
+            Some(cookies.join("; "))
+        }
+    }
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CookieJar")
+            .field("cookies", &self.read().iter_unexpired().count())
+            .finish()
+    }
+}
+
+fn to_url(uri: &Uri) -> Option<Url> {
+    Url::parse(&uri.to_string()).ok()
+}
@@ -0,0 +1,362 @@
+//! Decoding of compressed response bodies.
+//!
+//! The encodings supported depend on the enabled cargo features:
+//! `gzip`, `deflate`, `brotli` and `zstd`.
+
+use futures::{Async, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{self, Chunk, StatusCode};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The encodings advertised in `Accept-Encoding`.
+const ENCODINGS: &[&str] = &[
+    #[cfg(feature = "gzip")]
+    "gzip",
+    #[cfg(feature = "deflate")]
+    "deflate",
+    #[cfg(feature = "brotli")]
+    "br",
+    #[cfg(feature = "zstd")]
+    "zstd",
+];
+
+/// Adds the `Accept-Encoding` header listing the supported encodings,
+/// unless the request sets it or asks for a range. Returns whether the
+/// response should be decoded.
+pub(crate) fn accept_encoding(headers: &mut HeaderMap) -> bool {
+    if ENCODINGS.is_empty()
+        || headers.contains_key(header::ACCEPT_ENCODING)
+        || headers.contains_key(header::RANGE)
+    {
+        return false;
+    }
+
+    headers.insert(
+        header::ACCEPT_ENCODING,
+        HeaderValue::from_str(&ENCODINGS.join(", ")).expect("encodings are valid header values"),
+    );
+    true
+}
+
+/// The maximum size of a decoded body, shared with its `Response` so
+/// that it can be changed until the body is read.
+#[derive(Debug, Clone)]
+pub(crate) struct Limit(Arc<AtomicU64>);
+
+impl Limit {
+    pub(crate) fn new(limit: Option<u64>) -> Limit {
+        Limit(Arc::new(AtomicU64::new(limit.unwrap_or(u64::MAX))))
+    }
+
+    pub(crate) fn set(&self, limit: Option<u64>) {
+        self.0.store(limit.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            limit => Some(limit),
+        }
+    }
+}
+
+/// Decodes the body of `response` if it is compressed with a supported
+/// encoding, removing the `Content-Encoding` and `Content-Length` headers.
+/// Responses without a body, like `204` and `304` ones, are left as is.
+///
+/// The decoded body fails with a `BodyTooLarge` error as soon as it
+/// decodes more bytes than `limit`.
+pub(crate) fn decode(
+    response: hyper::Response<hyper::Body>,
+    limit: &Limit,
+) -> hyper::Response<hyper::Body> {
+    if response.status() == StatusCode::NO_CONTENT
+        || response.status() == StatusCode::NOT_MODIFIED
+        || response.body().is_end_stream()
+    {
+        return response;
+    }
+
+    let decoder = match response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+        .and_then(|encoding| decoder(encoding.trim(), Output::new(limit.clone())))
+    {
+        Some(decoder) => decoder,
+        None => return response,
+    };
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let body = DecodedBody {
+        body,
+        decoder: Some(decoder),
+        empty: true,
+    };
+    hyper::Response::from_parts(parts, hyper::Body::wrap_stream(body))
+}
+
+/// The buffer decoders write to, failing writes past the size limit so
+/// that a small compressed body can't be decoded into a huge one.
+struct Output {
+    decoded: Vec<u8>,
+    total: u64,
+    limit: Limit,
+}
+
+impl Output {
+    fn new(limit: Limit) -> Output {
+        Output {
+            decoded: Vec::new(),
+            total: 0,
+            limit,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.total += buf.len() as u64;
+        match self.limit.get() {
+            Some(limit) if self.total > limit => {
+                Err(io::Error::new(io::ErrorKind::Other, BodyTooLarge(limit)))
+            }
+            _ => {
+                self.decoded.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A decoder writing the decoded bytes to an `Output`.
+trait Decode: Write + Send {
+    /// Returns the bytes decoded so far.
+    fn decoded(&mut self) -> &mut Vec<u8>;
+
+    /// Checks that the encoded data is complete and
+    /// decodes the remaining bytes.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[cfg(feature = "gzip")]
+impl Decode for flate2::write::GzDecoder<Output> {
+    fn decoded(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().decoded
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+}
+
+/// A zlib decoder, `flate2::write::ZlibDecoder` doesn't tell
+/// whether the stream is complete.
+#[cfg(feature = "deflate")]
+struct ZlibDecoder {
+    decompress: flate2::Decompress,
+    buffer: Vec<u8>,
+    output: Output,
+    finished: bool,
+}
+
+#[cfg(feature = "deflate")]
+impl Write for ZlibDecoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+        // Bytes after the end of the stream are ignored.
+        while !self.finished {
+            self.buffer.clear();
+            let total_in = self.decompress.total_in();
+            let status = self
+                .decompress
+                .decompress_vec(input, &mut self.buffer, flate2::FlushDecompress::None)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            input = &input[(self.decompress.total_in() - total_in) as usize..];
+            self.finished = status == flate2::Status::StreamEnd;
+            self.output.write_all(&self.buffer)?;
+
+            // Keep going while output is pending.
+            if input.is_empty() && self.buffer.len() < self.buffer.capacity() {
+                break;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "deflate")]
+impl Decode for ZlibDecoder {
+    fn decoded(&mut self) -> &mut Vec<u8> {
+        &mut self.output.decoded
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "incomplete deflate stream",
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl Decode for brotli::DecompressorWriter<Output> {
+    fn decoded(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().decoded
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+}
+
+// `zstd::stream::write::Decoder` doesn't tell whether the last frame
+// is complete, its `zio::Writer` does.
+#[cfg(feature = "zstd")]
+impl Decode for zstd::stream::zio::Writer<Output, zstd::stream::raw::Decoder> {
+    fn decoded(&mut self) -> &mut Vec<u8> {
+        &mut self.writer_mut().decoded
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        zstd::stream::zio::Writer::finish(self)
+    }
+}
+
+/// Returns a decoder for `encoding` writing to `output`, or `None` if it
+/// isn't supported.
+#[cfg_attr(
+    not(any(
+        feature = "gzip",
+        feature = "deflate",
+        feature = "brotli",
+        feature = "zstd"
+    )),
+    allow(unused_variables)
+)]
+fn decoder(encoding: &str, output: Output) -> Option<Box<dyn Decode>> {
+    match encoding.to_lowercase().as_str() {
+        #[cfg(feature = "gzip")]
+        "gzip" | "x-gzip" => Some(Box::new(flate2::write::GzDecoder::new(output))),
+        #[cfg(feature = "deflate")]
+        "deflate" => Some(Box::new(ZlibDecoder {
+            decompress: flate2::Decompress::new(true),
+            buffer: Vec::with_capacity(8 * 1024),
+            output,
+            finished: false,
+        })),
+        #[cfg(feature = "brotli")]
+        "br" => Some(Box::new(brotli::DecompressorWriter::new(output, 4096))),
+        #[cfg(feature = "zstd")]
+        "zstd" => zstd::stream::raw::Decoder::new().ok().map(|decoder| {
+            Box::new(zstd::stream::zio::Writer::new(output, decoder)) as Box<dyn Decode>
+        }),
+        _ => None,
+    }
+}
+
+/// A response body decoded while it is read.
+struct DecodedBody {
+    body: hyper::Body,
+    decoder: Option<Box<dyn Decode>>,
+    // Empty bodies aren't encoded, even with a `Content-Encoding`.
+    empty: bool,
+}
+
+impl Stream for DecodedBody {
+    type Item = Chunk;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let decoder = match self.decoder {
+                Some(ref mut decoder) => decoder,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let decoded = match self.body.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    self.empty &= chunk.is_empty();
+                    decoder.write_all(&chunk).map_err(decode_error)?;
+                    mem::take(decoder.decoded())
+                }
+                Async::Ready(None) => {
+                    if !self.empty {
+                        decoder.finish().map_err(decode_error)?;
+                    }
+                    let decoded = mem::take(decoder.decoded());
+                    self.decoder = None;
+                    decoded
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+
+            if !decoded.is_empty() {
+                return Ok(Async::Ready(Some(Chunk::from(decoded))));
+            }
+        }
+    }
+}
+
+/// Returns the `BodyTooLarge` error a decoder failed with, or a
+/// `DecodeError` for any other error.
+fn decode_error(error: io::Error) -> Box<dyn StdError + Send + Sync> {
+    let limit = error
+        .get_ref()
+        .and_then(|error| error.downcast_ref::<BodyTooLarge>())
+        .map(|error| error.0);
+    match limit {
+        Some(limit) => Box::new(BodyTooLarge(limit)),
+        None => Box::new(DecodeError(error)),
+    }
+}
+
+/// The error returned when a decoded response body exceeds the
+/// maximum body size.
+#[derive(Debug)]
+pub(crate) struct BodyTooLarge(pub(crate) u64);
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the body exceeds the limit of {} bytes", self.0)
+    }
+}
+
+impl StdError for BodyTooLarge {}
+
+/// The error returned when a response body couldn't be decompressed.
+#[derive(Debug)]
+pub(crate) struct DecodeError(io::Error);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't decompress the response body: {}", self.0)
+    }
+}
+
+impl StdError for DecodeError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.0)
+    }
+}
@@ -0,0 +1,626 @@
+//! DNS resolution
+//!
+//! By default host names are resolved with the blocking `getaddrinfo`
+//! on a thread pool, see `SystemResolver`. `AsyncResolver` queries name
+//! servers directly without blocking a thread. Other resolvers can be
+//! plugged in by implementing `Resolve` and passing them to
+//! `HttperClientBuilder::dns_resolver`.
+//!
+//! Single host names can be pinned to addresses with
+//! `HttperClientBuilder::resolve`, which keeps the host in the
+//! `Host` header and the TLS server name.
+
+use client::error::Error;
+use futures::future::{self, Either};
+use futures::{Async, Future, Poll};
+use hyper::client::connect::dns::{self as hyper_dns, Name};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use std::vec;
+use tokio::io as tokio_io;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::timer::Timeout;
+
+/// The future returned by `Resolve::resolve`.
+pub type ResolveFuture = Box<dyn Future<Item = Vec<IpAddr>, Error = io::Error> + Send>;
+
+/// Resolves host names to IP addresses.
+///
+/// # Examples
+///
+/// ```
+/// extern crate futures;
+/// extern crate httper;
+///
+/// use futures::future;
+/// use httper::client::dns::{Resolve, ResolveFuture};
+/// use httper::client::HttperClient;
+/// use std::net::{IpAddr, Ipv4Addr};
+///
+/// struct Loopback;
+///
+/// impl Resolve for Loopback {
+///     fn resolve(&self, _host: &str) -> ResolveFuture {
+///         Box::new(future::ok(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]))
+///     }
+/// }
+///
+/// fn main() {
+///     let httper_client = HttperClient::builder()
+///         .dns_resolver(Loopback)
+///         .build()
+///         .unwrap();
+/// }
+/// ```
+pub trait Resolve: Send + Sync {
+    /// Resolves `host` to the addresses to try to connect to, in order.
+    fn resolve(&self, host: &str) -> ResolveFuture;
+}
+
+/// Resolves host names with the blocking `getaddrinfo`
+/// on a thread pool of its own.
+#[derive(Clone)]
+pub struct SystemResolver(hyper_dns::GaiResolver);
+
+impl SystemResolver {
+    /// Creates a resolver using `threads` threads.
+    pub fn new(threads: usize) -> SystemResolver {
+        SystemResolver(hyper_dns::GaiResolver::new(threads))
+    }
+}
+
+impl fmt::Debug for SystemResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("SystemResolver")
+    }
+}
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str) -> ResolveFuture {
+        let name = match host.parse::<Name>() {
+            Ok(name) => name,
+            Err(error) => {
+                return Box::new(future::err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    error,
+                )))
+            }
+        };
+
+        Box::new(hyper_dns::Resolve::resolve(&self.0, name).map(|addrs| addrs.collect::<Vec<_>>()))
+    }
+}
+
+/// Resolves host names by querying name servers over UDP,
+/// without blocking a thread.
+///
+/// Names found in the hosts file are answered from it. Both `A` and
+/// `AAAA` records are queried, the name servers are tried in order and
+/// answers too long for a datagram are asked again over TCP.
+///
+/// Host names without a trailing dot are also looked up in the search
+/// domains: first when they have fewer dots than `ndots`, like
+/// Kubernetes service names, and after the name itself otherwise.
+#[derive(Debug, Clone)]
+pub struct AsyncResolver {
+    name_servers: Arc<Vec<SocketAddr>>,
+    hosts: Arc<HashMap<String, Vec<IpAddr>>>,
+    search: Arc<Vec<String>>,
+    ndots: usize,
+    timeout: Duration,
+}
+
+impl AsyncResolver {
+    /// Creates a resolver querying `name_servers`.
+    pub fn new(name_servers: Vec<SocketAddr>) -> AsyncResolver {
+        AsyncResolver {
+            name_servers: Arc::new(name_servers),
+            hosts: Arc::new(HashMap::new()),
+            search: Arc::new(Vec::new()),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Creates a resolver using the name servers, search domains and
+    /// `ndots` option from `/etc/resolv.conf` and the addresses from
+    /// `/etc/hosts`.
+    ///
+    /// # Errors
+    /// Will return Err if `/etc/resolv.conf` can't be read or
+    /// doesn't list any name server.
+    pub fn from_system_conf() -> Result<AsyncResolver, Error> {
+        let resolv_conf = fs::read_to_string("/etc/resolv.conf").map_err(Error::builder)?;
+        let conf = parse_resolv_conf(&resolv_conf);
+        if conf.name_servers.is_empty() {
+            return Err(Error::builder("no name server found in /etc/resolv.conf"));
+        }
+
+        let hosts = fs::read_to_string("/etc/hosts")
+            .map(|hosts| parse_hosts(&hosts))
+            .unwrap_or_default();
+
+        Ok(AsyncResolver {
+            hosts: Arc::new(hosts),
+            ..AsyncResolver::new(conf.name_servers)
+                .search(conf.search)
+                .ndots(conf.ndots)
+        })
+    }
+
+    /// Sets the domains host names are looked up in, in order.
+    ///
+    /// Defaults to none.
+    pub fn search(self, domains: Vec<String>) -> AsyncResolver {
+        let domains = domains
+            .iter()
+            .map(|domain| domain.trim_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        AsyncResolver {
+            search: Arc::new(domains),
+            ..self
+        }
+    }
+
+    /// Sets the number of dots from which host names are looked up as
+    /// they are before being looked up in the search domains.
+    ///
+    /// Defaults to 1.
+    pub fn ndots(self, ndots: usize) -> AsyncResolver {
+        AsyncResolver { ndots, ..self }
+    }
+
+    /// Sets for how long each name server is waited on.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn timeout(self, timeout: Duration) -> AsyncResolver {
+        AsyncResolver { timeout, ..self }
+    }
+
+    /// Returns the names to look `host` up with, in order.
+    fn names(&self, host: &str, fully_qualified: bool) -> Vec<String> {
+        if fully_qualified {
+            return vec![host.to_string()];
+        }
+
+        let searched = self
+            .search
+            .iter()
+            .map(|domain| format!("{}.{}", host, domain));
+        if host.matches('.').count() >= self.ndots {
+            iter::once(host.to_string()).chain(searched).collect()
+        } else {
+            searched.chain(iter::once(host.to_string())).collect()
+        }
+    }
+
+    /// Queries the name servers in order for `host` until one answers.
+    fn query_name_servers(&self, host: String) -> ResolveFuture {
+        let timeout = self.timeout;
+        let not_found = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for {}", host),
+        );
+
+        self.name_servers.iter().cloned().fold(
+            Box::new(future::err(not_found)),
+            move |previous: ResolveFuture, name_server| {
+                let host = host.clone();
+                Box::new(previous.or_else(move |_| {
+                    let ipv4 = query(name_server, &host, RecordType::A, timeout);
+                    let ipv6 = query(name_server, &host, RecordType::Aaaa, timeout);
+
+                    ipv4.join(ipv6).and_then(move |(mut addrs, ipv6)| {
+                        addrs.extend(ipv6);
+                        if addrs.is_empty() {
+                            Err(io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!("no addresses found for {}", host),
+                            ))
+                        } else {
+                            Ok(addrs)
+                        }
+                    })
+                }))
+            },
+        )
+    }
+}
+
+impl Resolve for AsyncResolver {
+    fn resolve(&self, host: &str) -> ResolveFuture {
+        let fully_qualified = host.ends_with('.');
+        let host = host.trim_end_matches('.').to_lowercase();
+
+        if let Some(addrs) = self.hosts.get(&host) {
+            return Box::new(future::ok(addrs.clone()));
+        }
+
+        let not_found = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for {}", host),
+        );
+
+        // Try the names in order until one resolves.
+        let resolver = self.clone();
+        self.names(&host, fully_qualified).into_iter().fold(
+            Box::new(future::err(not_found)),
+            move |previous: ResolveFuture, name| {
+                let resolver = resolver.clone();
+                Box::new(previous.or_else(move |_| resolver.query_name_servers(name)))
+            },
+        )
+    }
+}
+
+/// The settings of `/etc/resolv.conf` used by the `AsyncResolver`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ResolvConf {
+    pub(crate) name_servers: Vec<SocketAddr>,
+    pub(crate) search: Vec<String>,
+    pub(crate) ndots: usize,
+}
+
+pub(crate) fn parse_resolv_conf(resolv_conf: &str) -> ResolvConf {
+    let mut conf = ResolvConf {
+        name_servers: Vec::new(),
+        search: Vec::new(),
+        ndots: 1,
+    };
+
+    for line in resolv_conf.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => {
+                if let Some(ip) = fields.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+                    conf.name_servers.push(SocketAddr::new(ip, 53));
+                }
+            }
+            // The last `domain` or `search` line wins.
+            Some("domain") | Some("search") => {
+                conf.search = fields
+                    .map(|domain| domain.trim_end_matches('.').to_lowercase())
+                    .collect();
+            }
+            Some("options") => {
+                for option in fields {
+                    if let Some(ndots) = option
+                        .strip_prefix("ndots:")
+                        .and_then(|ndots| ndots.parse::<usize>().ok())
+                    {
+                        conf.ndots = ndots.min(15);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    conf
+}
+
+fn parse_hosts(hosts: &str) -> HashMap<String, Vec<IpAddr>> {
+    let mut entries: HashMap<String, Vec<IpAddr>> = HashMap::new();
+
+    for line in hosts.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let ip = match fields.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+            Some(ip) => ip,
+            None => continue,
+        };
+
+        for name in fields {
+            entries.entry(name.to_lowercase()).or_default().push(ip);
+        }
+    }
+
+    entries
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecordType {
+    A = 1,
+    Aaaa = 28,
+}
+
+/// Queries `name_server` for the `record_type` records of `host`.
+fn query(
+    name_server: SocketAddr,
+    host: &str,
+    record_type: RecordType,
+    timeout: Duration,
+) -> Box<dyn Future<Item = Vec<IpAddr>, Error = io::Error> + Send> {
+    let id = RandomState::new().build_hasher().finish() as u16;
+
+    let message = match encode_query(id, host, record_type) {
+        Ok(message) => message,
+        Err(error) => return Box::new(future::err(error)),
+    };
+
+    let bind_addr = match name_server {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    let socket = match UdpSocket::bind(&bind_addr).and_then(|socket| {
+        socket.connect(&name_server)?;
+        Ok(socket)
+    }) {
+        Ok(socket) => socket,
+        Err(error) => return Box::new(future::err(error)),
+    };
+
+    let query = Query {
+        socket,
+        message: message.clone(),
+        sent: false,
+        id,
+        record_type,
+    };
+    let query = query.and_then(move |answer| match answer {
+        Answer::Addrs(addrs) => Either::A(future::ok(addrs)),
+        Answer::Truncated => Either::B(query_tcp(name_server, &message, id, record_type)),
+    });
+
+    Box::new(Timeout::new(query, timeout).map_err(|error| {
+        error
+            .into_inner()
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))
+    }))
+}
+
+/// Queries `name_server` over TCP with `message`, for answers
+/// too long for a datagram.
+fn query_tcp(
+    name_server: SocketAddr,
+    message: &[u8],
+    id: u16,
+    record_type: RecordType,
+) -> impl Future<Item = Vec<IpAddr>, Error = io::Error> {
+    // Messages are prefixed with their length over TCP.
+    let mut request = vec![(message.len() >> 8) as u8, message.len() as u8];
+    request.extend_from_slice(message);
+
+    TcpStream::connect(&name_server)
+        .and_then(move |stream| tokio_io::write_all(stream, request))
+        .and_then(|(stream, _)| tokio_io::read_exact(stream, [0; 2]))
+        .and_then(|(stream, length)| {
+            let length = usize::from(length[0]) << 8 | usize::from(length[1]);
+            tokio_io::read_exact(stream, vec![0; length])
+        })
+        .and_then(
+            move |(_, answer)| match decode_answer(&answer, id, record_type)? {
+                Some(Answer::Addrs(addrs)) => Ok(addrs),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid DNS answer",
+                )),
+            },
+        )
+}
+
+/// What a name server answered to a query.
+enum Answer {
+    Addrs(Vec<IpAddr>),
+    /// The answer didn't fit in a datagram.
+    Truncated,
+}
+
+/// A future sending a DNS query and waiting for its answer.
+struct Query {
+    socket: UdpSocket,
+    message: Vec<u8>,
+    sent: bool,
+    id: u16,
+    record_type: RecordType,
+}
+
+impl Future for Query {
+    type Item = Answer;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if !self.sent {
+            if let Async::NotReady = self.socket.poll_send(&self.message)? {
+                return Ok(Async::NotReady);
+            }
+            self.sent = true;
+        }
+
+        // Without EDNS answers over UDP are at most 512 bytes long,
+        // longer ones are truncated and flagged as such.
+        let mut buf = [0; 512];
+        loop {
+            let read = match self.socket.poll_recv(&mut buf)? {
+                Async::Ready(read) => read,
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+
+            // Ignore stray datagrams not answering our query.
+            if let Some(answer) = decode_answer(&buf[..read], self.id, self.record_type)? {
+                return Ok(Async::Ready(answer));
+            }
+        }
+    }
+}
+
+fn encode_query(id: u16, host: &str, record_type: RecordType) -> io::Result<Vec<u8>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid host: {}", host),
+        )
+    };
+
+    // The header asks for recursion with a single question.
+    let mut message = vec![(id >> 8) as u8, id as u8, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    // Fully qualified names end with a dot.
+    let name = host.strip_suffix('.').unwrap_or(host);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid());
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+
+    if message.len() > 12 + 255 {
+        return Err(invalid());
+    }
+
+    message.extend_from_slice(&[0, record_type as u8, 0, 1]);
+    Ok(message)
+}
+
+/// Decodes the addresses of `record_type` in the answer to the query `id`.
+/// Returns `None` for messages not answering it.
+fn decode_answer(message: &[u8], id: u16, record_type: RecordType) -> io::Result<Option<Answer>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid DNS answer");
+
+    if message.len() < 12 || u16::from(message[0]) << 8 | u16::from(message[1]) != id {
+        return Ok(None);
+    }
+
+    // Not a response.
+    if message[2] & 0x80 == 0 {
+        return Ok(None);
+    }
+
+    if message[2] & 0x02 != 0 {
+        return Ok(Some(Answer::Truncated));
+    }
+
+    match message[3] & 0x0f {
+        0 => {}
+        // The name doesn't exist.
+        3 => return Ok(Some(Answer::Addrs(Vec::new()))),
+        rcode => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("DNS query failed with response code {}", rcode),
+            ))
+        }
+    }
+
+    let questions = u16::from(message[4]) << 8 | u16::from(message[5]);
+    let answers = u16::from(message[6]) << 8 | u16::from(message[7]);
+
+    let mut position = 12;
+    for _ in 0..questions {
+        position = skip_name(message, position).ok_or_else(invalid)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        position = skip_name(message, position).ok_or_else(invalid)?;
+        let record = message.get(position..position + 10).ok_or_else(invalid)?;
+        let kind = u16::from(record[0]) << 8 | u16::from(record[1]);
+        let length = (usize::from(record[8]) << 8) | usize::from(record[9]);
+        position += 10;
+        let data = message
+            .get(position..position + length)
+            .ok_or_else(invalid)?;
+        position += length;
+
+        // The answer can also contain the `CNAME` records leading to the addresses.
+        match (kind, data.len()) {
+            (1, 4) if record_type == RecordType::A => {
+                addrs.push(IpAddr::V4(Ipv4Addr::new(
+                    data[0], data[1], data[2], data[3],
+                )));
+            }
+            (28, 16) if record_type == RecordType::Aaaa => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                addrs.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Some(Answer::Addrs(addrs)))
+}
+
+/// Returns the position following the name starting at `position`.
+fn skip_name(message: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(position)?;
+        match length {
+            0 => return Some(position + 1),
+            // A pointer to a name elsewhere in the message ends the name.
+            length if length & 0xc0 == 0xc0 => return Some(position + 2),
+            length => position += 1 + usize::from(length),
+        }
+    }
+}
+
+/// The DNS settings of a `HttperClientBuilder`.
+#[derive(Clone, Default)]
+pub(crate) struct DnsConfig {
+    pub(crate) overrides: HashMap<String, Vec<IpAddr>>,
+    pub(crate) resolver: Option<Arc<dyn Resolve>>,
+}
+
+impl fmt::Debug for DnsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsConfig")
+            .field("overrides", &self.overrides)
+            .field("resolver", &self.resolver.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+impl DnsConfig {
+    /// Builds the `DnsResolver` used by the connector, falling back to
+    /// a `SystemResolver` with `dns_threads` threads.
+    pub(crate) fn build_resolver(&self, dns_threads: usize) -> DnsResolver {
+        DnsResolver {
+            overrides: Arc::new(self.overrides.clone()),
+            resolver: self
+                .resolver
+                .clone()
+                .unwrap_or_else(|| Arc::new(SystemResolver::new(dns_threads))),
+        }
+    }
+}
+
+/// The resolver used by the connector, applying the overrides
+/// before asking the configured `Resolve` implementation.
+#[derive(Clone)]
+pub struct DnsResolver {
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    resolver: Arc<dyn Resolve>,
+}
+
+impl fmt::Debug for DnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsResolver")
+            .field("overrides", &self.overrides)
+            .finish()
+    }
+}
+
+impl hyper_dns::Resolve for DnsResolver {
+    type Addrs = vec::IntoIter<IpAddr>;
+    type Future = Box<dyn Future<Item = Self::Addrs, Error = io::Error> + Send>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let addrs = match self.overrides.get(&name.as_str().to_lowercase()) {
+            Some(addrs) => Box::new(future::ok(addrs.clone())),
+            None => self.resolver.resolve(name.as_str()),
+        };
+
+        Box::new(addrs.map(|addrs| addrs.into_iter()))
+    }
+}
@@ -0,0 +1,392 @@
+//! Request errors
+//!
+//! Requests fail with an `Error` telling what went wrong with its
+//! `kind`, the method and url of the request when they are known, and
+//! the error that caused it as its `source`, like a `TimeoutError`, a
+//! `RedirectError`, an `OAuth2Error`, a `hyper::Error` or a
+//! `serde_json::Error`.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate httper;
+//! extern crate tokio;
+//!
+//! use httper::client::HttperClient;
+//! use httper::ErrorKind;
+//! use tokio::runtime::Runtime;
+//!
+//! fn main() {
+//!     let mut rt = Runtime::new().unwrap();
+//!
+//!     let httper_client = HttperClient::new();
+//!
+//!     let result = rt.block_on(httper_client.get("http://localhost:1").send());
+//!
+//!     match result {
+//!         Ok(response) => println!("Status: {}", response.status()),
+//!         Err(ref error) if error.is_connect() => println!("Couldn't connect: {}", error),
+//!         Err(ref error) if error.kind() == ErrorKind::Builder => println!("Bad request: {}", error),
+//!         Err(error) => println!("Request failed: {}", error),
+//!     }
+//! }
+//! ```
+
+use client::decompress::{BodyTooLarge, DecodeError};
+use client::oauth2::OAuth2Error;
+use client::redact::{redact_uri, SensitiveHeaders};
+use client::redirect::RedirectError;
+use client::timeout::TimeoutError;
+use failure;
+use http;
+use hyper::header::HeaderMap;
+use hyper::{self, Method, StatusCode, Uri};
+use serde_json;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// The number of bytes of the response body kept in status errors.
+pub(crate) const BODY_SNIPPET_LENGTH: usize = 1024;
+
+/// What went wrong with a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The request couldn't be built, because of an invalid url or header,
+    /// or the client or one of its settings, like a `Proxy` or a
+    /// `Certificate`, couldn't be built.
+    Builder,
+    /// A connection couldn't be established, because of a DNS, TCP,
+    /// TLS or proxy failure.
+    Connect,
+    /// A timeout elapsed, the source is a `TimeoutError`.
+    Timeout,
+    /// A redirect couldn't be followed, the source is a `RedirectError`.
+    Redirect,
+    /// No credentials could be obtained, the source is an `OAuth2Error`.
+    Auth,
+    /// The request failed while it was sent or its response received.
+    Request,
+    /// The response body couldn't be read.
+    Body,
+    /// The response body couldn't be decompressed or deserialized.
+    Decode,
+    /// The response body is larger than the maximum size allowed
+    /// for buffered reads.
+    BodyTooLarge,
+    /// The response has an error status, its headers and the start of
+    /// its body are kept in the error.
+    Status(StatusCode),
+    /// A `CookieJar` couldn't be loaded or saved, or a cookie couldn't
+    /// be added to it.
+    Cookie,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Builder => f.write_str("builder error"),
+            ErrorKind::Connect => f.write_str("connect error"),
+            ErrorKind::Timeout => f.write_str("timeout"),
+            ErrorKind::Redirect => f.write_str("redirect error"),
+            ErrorKind::Auth => f.write_str("authentication error"),
+            ErrorKind::Request => f.write_str("request error"),
+            ErrorKind::Body => f.write_str("body error"),
+            ErrorKind::Decode => f.write_str("decode error"),
+            ErrorKind::BodyTooLarge => f.write_str("body too large"),
+            ErrorKind::Status(status) => write!(f, "status {}", status),
+            ErrorKind::Cookie => f.write_str("cookie error"),
+        }
+    }
+}
+
+/// The error returned by requests, see the module documentation.
+pub struct Error {
+    inner: Box<Inner>,
+}
+
+struct Inner {
+    kind: ErrorKind,
+    method: Option<Method>,
+    url: Option<Uri>,
+    headers: Option<HeaderMap>,
+    body_snippet: Option<String>,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+    sensitive_headers: Option<Arc<SensitiveHeaders>>,
+}
+
+impl Error {
+    pub(crate) fn new<E>(kind: ErrorKind, source: E) -> Error
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Error {
+            inner: Box::new(Inner {
+                kind,
+                method: None,
+                url: None,
+                headers: None,
+                body_snippet: None,
+                source: Some(source.into()),
+                sensitive_headers: None,
+            }),
+        }
+    }
+
+    /// Creates the error of a client setting that couldn't be built.
+    pub(crate) fn builder<E>(source: E) -> Error
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Error::new(ErrorKind::Builder, source)
+    }
+
+    /// Converts an error of the layers building the client, keeping
+    /// the `Error`s they return as they are.
+    pub(crate) fn builder_from_failure(error: failure::Error) -> Error {
+        match error.downcast::<Error>() {
+            Ok(error) => error,
+            Err(error) => Error::builder(into_source(error)),
+        }
+    }
+
+    /// Creates the error of a `CookieJar` operation.
+    pub(crate) fn cookie<E>(source: E) -> Error
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Error::new(ErrorKind::Cookie, source)
+    }
+
+    /// Converts an error of the cookie store.
+    pub(crate) fn cookie_from_failure(error: failure::Error) -> Error {
+        Error::cookie(into_source(error))
+    }
+
+    /// Creates the error of a response with an error status, keeping
+    /// at most `BODY_SNIPPET_LENGTH` bytes of its `body`.
+    pub(crate) fn from_status(status: StatusCode, headers: HeaderMap, body: &[u8]) -> Error {
+        let body = &body[..body.len().min(BODY_SNIPPET_LENGTH)];
+        Error {
+            inner: Box::new(Inner {
+                kind: ErrorKind::Status(status),
+                method: None,
+                url: None,
+                headers: Some(headers),
+                body_snippet: Some(String::from_utf8_lossy(body).into_owned()),
+                source: None,
+                sensitive_headers: None,
+            }),
+        }
+    }
+
+    /// Converts an error of the layers sending requests, keeping the
+    /// `Error`s returned by middleware as they are.
+    pub(crate) fn from_failure(error: failure::Error) -> Error {
+        let error = match error.downcast::<Error>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        let kind = if error.downcast_ref::<TimeoutError>().is_some() {
+            ErrorKind::Timeout
+        } else if error.downcast_ref::<RedirectError>().is_some() {
+            ErrorKind::Redirect
+        } else if error.downcast_ref::<OAuth2Error>().is_some() {
+            ErrorKind::Auth
+        } else if error.downcast_ref::<serde_json::Error>().is_some() {
+            ErrorKind::Decode
+        } else {
+            match error.downcast_ref::<hyper::Error>() {
+                Some(error) if error.is_connect() => ErrorKind::Connect,
+                Some(error) if find::<DecodeError>(error).is_some() => ErrorKind::Decode,
+                _ => ErrorKind::Request,
+            }
+        };
+
+        Error::new(kind, into_source(error))
+    }
+
+    /// Converts an error reading a response body.
+    pub(crate) fn from_body(error: hyper::Error) -> Error {
+        if let Some(timeout) = find::<TimeoutError>(&error) {
+            return Error::new(ErrorKind::Timeout, *timeout);
+        }
+        if let Some(error) = find::<BodyTooLarge>(&error) {
+            return Error::body_too_large(error.0);
+        }
+
+        let kind = if find::<DecodeError>(&error).is_some() {
+            ErrorKind::Decode
+        } else {
+            ErrorKind::Body
+        };
+        Error::new(kind, error)
+    }
+
+    /// Creates the error of a response body larger than `limit` bytes.
+    pub(crate) fn body_too_large(limit: u64) -> Error {
+        Error::new(
+            ErrorKind::BodyTooLarge,
+            format!("the body exceeds the limit of {} bytes", limit),
+        )
+    }
+
+    /// Sets the method and url of the request, and the sensitive headers
+    /// of the client masked in the `Debug` output, unless they are
+    /// already set.
+    pub(crate) fn with_request(
+        mut self,
+        method: Option<Method>,
+        url: Option<Uri>,
+        sensitive_headers: &Arc<SensitiveHeaders>,
+    ) -> Error {
+        if self.inner.method.is_none() {
+            self.inner.method = method;
+        }
+        if self.inner.url.is_none() {
+            self.inner.url = url;
+        }
+        if self.inner.sensitive_headers.is_none() {
+            self.inner.sensitive_headers = Some(sensitive_headers.clone());
+        }
+        self
+    }
+
+    /// Returns what went wrong.
+    pub fn kind(&self) -> ErrorKind {
+        self.inner.kind
+    }
+
+    /// Returns the method of the request, if known.
+    pub fn method(&self) -> Option<&Method> {
+        self.inner.method.as_ref()
+    }
+
+    /// Returns the url of the request, if known.
+    pub fn url(&self) -> Option<&Uri> {
+        self.inner.url.as_ref()
+    }
+
+    /// Returns the status of the response for status errors.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self.inner.kind {
+            ErrorKind::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Returns the headers of the response for status errors.
+    pub fn headers(&self) -> Option<&HeaderMap> {
+        self.inner.headers.as_ref()
+    }
+
+    /// Returns the start of the response body for status errors, at most
+    /// 1024 bytes of it, with invalid UTF-8 sequences replaced.
+    pub fn body_snippet(&self) -> Option<&str> {
+        self.inner.body_snippet.as_deref()
+    }
+
+    /// Returns true if a timeout elapsed.
+    pub fn is_timeout(&self) -> bool {
+        self.inner.kind == ErrorKind::Timeout
+    }
+
+    /// Returns true if no connection could be established.
+    pub fn is_connect(&self) -> bool {
+        self.inner.kind == ErrorKind::Connect
+    }
+
+    /// Returns true if the response has an error status.
+    pub fn is_status(&self) -> bool {
+        self.status().is_some()
+    }
+
+    /// Returns true if the response body couldn't be decoded.
+    pub fn is_decode(&self) -> bool {
+        self.inner.kind == ErrorKind::Decode
+    }
+
+    /// Returns true if the response body exceeds the maximum size
+    /// allowed for buffered reads.
+    pub fn is_body_too_large(&self) -> bool {
+        self.inner.kind == ErrorKind::BodyTooLarge
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let default = SensitiveHeaders::default();
+        let sensitive = self.inner.sensitive_headers.as_deref().unwrap_or(&default);
+        f.debug_struct("Error")
+            .field("kind", &self.inner.kind)
+            .field("method", &self.inner.method)
+            .field("url", &self.inner.url.as_ref().map(redact_uri))
+            .field(
+                "headers",
+                &self
+                    .inner
+                    .headers
+                    .as_ref()
+                    .map(|headers| sensitive.redact(headers)),
+            )
+            .field("body_snippet", &self.inner.body_snippet)
+            .field("source", &self.inner.source)
+            .finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.inner.kind)?;
+        match (&self.inner.method, &self.inner.url) {
+            (Some(method), Some(url)) => write!(f, " for {} {}", method, redact_uri(url))?,
+            (None, Some(url)) => write!(f, " for {}", redact_uri(url))?,
+            _ => {}
+        }
+        if let Some(ref source) = self.inner.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.inner
+            .source
+            .as_ref()
+            .map(|source| &**source as &(dyn StdError + 'static))
+    }
+}
+
+/// Finds an error of type `T` in the source chain of `error`.
+fn find<'a, T: StdError + 'static>(error: &'a (dyn StdError + 'static)) -> Option<&'a T> {
+    match error.downcast_ref::<T>() {
+        Some(error) => Some(error),
+        None => error.source().and_then(find),
+    }
+}
+
+/// Takes the error out of `error`, so that it can be downcast
+/// from `Error::source` and its own source chain is kept.
+fn into_source(error: failure::Error) -> Box<dyn StdError + Send + Sync> {
+    downcast::<hyper::Error>(error)
+        .or_else(downcast::<TimeoutError>)
+        .or_else(downcast::<RedirectError>)
+        .or_else(downcast::<OAuth2Error>)
+        .or_else(downcast::<serde_json::Error>)
+        .or_else(downcast::<http::Error>)
+        .or_else(downcast::<io::Error>)
+        .unwrap_or_else(Box::from)
+}
+
+fn downcast<T>(error: failure::Error) -> Result<Box<dyn StdError + Send + Sync>, failure::Error>
+where
+    T: StdError + Send + Sync + 'static,
+{
+    error
+        .downcast::<T>()
+        .map(|error| Box::new(error) as Box<dyn StdError + Send + Sync>)
+}
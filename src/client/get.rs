@@ -1,15 +1,44 @@
-use super::{Headers, HttperClient};
+use super::{Headers, HttperClient, RequestOptions};
+use client::auth::Auth;
+use client::error::Error;
 use client::response_future::ResponseFuture;
-use failure::Error;
+use client::retry::RetryPolicy;
+use client::timeout::Timeouts;
 use http;
 use hyper;
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
-#[derive(Debug)]
 pub struct Get<'a> {
     pub request_builder: Result<http::request::Builder, Error>,
     pub client: &'a HttperClient,
     pub headers: Headers,
+    pub timeouts: Timeouts,
+    pub retry_policy: Option<RetryPolicy>,
+    pub decompress: bool,
+    pub auth: Option<Auth>,
+}
+
+impl<'a> fmt::Debug for Get<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sensitive = &self.client.sensitive_headers;
+        f.debug_struct("Get")
+            .field(
+                "request_builder",
+                &self
+                    .request_builder
+                    .as_ref()
+                    .map(|builder| sensitive.redact_builder(builder)),
+            )
+            .field("client", &self.client)
+            .field("headers", &sensitive.redact_headers(&self.headers))
+            .field("timeouts", &self.timeouts)
+            .field("retry_policy", &self.retry_policy)
+            .field("decompress", &self.decompress)
+            .field("auth", &self.auth)
+            .finish()
+    }
 }
 
 impl<'a> Get<'a> {
@@ -22,6 +51,10 @@ impl<'a> Get<'a> {
             request_builder,
             client,
             headers: HashMap::new(),
+            timeouts: Timeouts::default(),
+            retry_policy: None,
+            decompress: true,
+            auth: None,
         }
     }
 
@@ -40,16 +73,116 @@ impl<'a> Get<'a> {
     /// httper_client.get("http://localhost:9090").headers(headers).send();
     /// ```
     pub fn headers(self, headers: Headers) -> Self {
+        Get { headers, ..self }
+    }
+
+    /// Sets the time allowed to establish a connection for this request,
+    /// overriding the client default.
+    ///
+    /// Exceeding it fails the request with `TimeoutError::Connect`.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        Get {
+            timeouts: Timeouts {
+                connect: Some(timeout),
+                ..self.timeouts
+            },
+            ..self
+        }
+    }
+
+    /// Sets the total time allowed for this request, overriding
+    /// the client default.
+    ///
+    /// Exceeding it fails the request with `TimeoutError::Request`.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Get {
+            timeouts: Timeouts {
+                request: Some(timeout),
+                ..self.timeouts
+            },
+            ..self
+        }
+    }
+
+    /// Sets the time allowed to pass between two reads of the
+    /// response body for this request, overriding the client default.
+    ///
+    /// Exceeding it fails the read with `TimeoutError::Read`.
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        Get {
+            timeouts: Timeouts {
+                read: Some(timeout),
+                ..self.timeouts
+            },
+            ..self
+        }
+    }
+
+    /// Sets the policy deciding whether this request is sent
+    /// again when it fails, overriding the client default.
+    pub fn retry(self, retry_policy: RetryPolicy) -> Self {
+        Get {
+            retry_policy: Some(retry_policy),
+            ..self
+        }
+    }
+
+    /// Controls whether a response compressed with an encoding enabled
+    /// by the `gzip`, `deflate`, `brotli` or `zstd` features is decoded.
+    /// Defaults to `true`.
+    ///
+    /// When disabled, `Accept-Encoding` isn't added to the request
+    /// and the response body is returned as received.
+    pub fn decompress(self, decompress: bool) -> Self {
+        Get { decompress, ..self }
+    }
+
+    /// Authenticates the request with HTTP Basic authentication,
+    /// overriding the client default, see the `auth` module.
+    pub fn basic_auth<U: Into<String>, P: Into<String>>(self, username: U, password: P) -> Self {
+        Get {
+            auth: Some(Auth::Basic {
+                username: username.into(),
+                password: password.into(),
+            }),
+            ..self
+        }
+    }
+
+    /// Authenticates the request with a bearer token,
+    /// overriding the client default, see the `auth` module.
+    pub fn bearer_auth<T: Into<String>>(self, token: T) -> Self {
+        Get {
+            auth: Some(Auth::Bearer(token.into())),
+            ..self
+        }
+    }
+
+    /// Authenticates the request with HTTP Digest authentication,
+    /// overriding the client default, see the `auth` module.
+    pub fn digest_auth<U: Into<String>, P: Into<String>>(self, username: U, password: P) -> Self {
         Get {
-            headers,
-            request_builder: self.request_builder,
-            client: self.client,
+            auth: Some(Auth::Digest {
+                username: username.into(),
+                password: password.into(),
+            }),
+            ..self
         }
     }
 
     /// Sends the request and returns a `ResponseFuture`.
     pub fn send(self) -> ResponseFuture {
-        self.client
-            .send_request(self.request_builder, hyper::Body::empty(), &self.headers)
+        self.client.send_request(
+            self.request_builder,
+            hyper::Body::empty(),
+            &self.headers,
+            RequestOptions {
+                auth: self.auth,
+                timeouts: self.timeouts,
+                retry_policy: self.retry_policy,
+                compression: None,
+                decompress: self.decompress,
+            },
+        )
     }
 }
@@ -0,0 +1,168 @@
+//! Middleware
+//!
+//! Middleware registered with `HttperClientBuilder::middleware` handle
+//! every request sent by the client, including retries and followed
+//! redirects, between the client adding its own headers and the request
+//! being signed and sent. They run in the order they were registered,
+//! each passing the request on to the next one with `Next::run`.
+//!
+//! A middleware can modify the request before passing it on, post-process
+//! the response future returned by `Next::run`, or answer the request
+//! itself, without sending it, by returning its own `ResponseFuture`.
+//!
+//! Closures taking the request and `Next` are middleware.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! extern crate httper;
+//! extern crate hyper;
+//!
+//! use futures::Future;
+//! use httper::client::middleware::Next;
+//! use httper::client::response_future::ResponseFuture;
+//! use httper::client::HttperClient;
+//! use std::time::Instant;
+//!
+//! fn main() {
+//!     let httper_client = HttperClient::builder()
+//!         .middleware(|mut request: hyper::Request<hyper::Body>, next: Next| {
+//!             request
+//!                 .headers_mut()
+//!                 .insert("x-request-id", "42".parse().unwrap());
+//!
+//!             let (method, uri, start) = (request.method().clone(), request.uri().clone(), Instant::now());
+//!             ResponseFuture(Box::new(next.run(request).map(move |response| {
+//!                 println!("{} {} {} in {:?}", method, uri, response.status(), start.elapsed());
+//!                 response
+//!             })))
+//!         })
+//!         .build()
+//!         .unwrap();
+//!
+//!     httper_client.get("https://testing.local").send();
+//! }
+//! ```
+
+use client::error::Error;
+use client::redact::SensitiveHeaders;
+use client::redirect::{SendFuture, SendRequest};
+use client::response::Response;
+use client::response_future::ResponseFuture;
+use failure;
+use futures::Future;
+use hyper;
+use std::fmt;
+use std::sync::Arc;
+
+/// Handles the requests sent by a client, see the module documentation.
+pub trait Middleware: Send + Sync + 'static {
+    /// Handles `request`, passing it on with `next.run` unless
+    /// the middleware answers it itself.
+    fn handle(&self, request: hyper::Request<hyper::Body>, next: Next) -> ResponseFuture;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(hyper::Request<hyper::Body>, Next) -> ResponseFuture + Send + Sync + 'static,
+{
+    fn handle(&self, request: hyper::Request<hyper::Body>, next: Next) -> ResponseFuture {
+        self(request, next)
+    }
+}
+
+/// The rest of the middleware chain, ending with sending the request.
+pub struct Next {
+    middlewares: Arc<Middlewares>,
+    index: usize,
+    send: SendRequest,
+    sensitive_headers: Arc<SensitiveHeaders>,
+}
+
+impl Next {
+    /// Passes `request` to the next middleware, or sends it
+    /// if this was the last one.
+    pub fn run(self, request: hyper::Request<hyper::Body>) -> ResponseFuture {
+        match self.middlewares.0.get(self.index).cloned() {
+            Some(middleware) => middleware.handle(
+                request,
+                Next {
+                    index: self.index + 1,
+                    ..self
+                },
+            ),
+            None => {
+                let method = request.method().clone();
+                let url = request.uri().clone();
+                let sensitive_headers = self.sensitive_headers;
+                let error_method = method.clone();
+                let error_sensitive_headers = sensitive_headers.clone();
+                let error_url = url.clone();
+                ResponseFuture(Box::new(
+                    (self.send)(request)
+                        .map(move |response| {
+                            Response::new(response, url)
+                                .with_method(method)
+                                .with_sensitive_headers(sensitive_headers)
+                        })
+                        .map_err(move |error| {
+                            Error::from_failure(error).with_request(
+                                Some(error_method),
+                                Some(error_url),
+                                &error_sensitive_headers,
+                            )
+                        }),
+                ))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Next {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("remaining", &(self.middlewares.0.len() - self.index))
+            .finish()
+    }
+}
+
+/// The middleware of a client, in the order they were registered.
+#[derive(Clone, Default)]
+pub(crate) struct Middlewares(Vec<Arc<dyn Middleware>>);
+
+impl Middlewares {
+    pub(crate) fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.0.push(middleware);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Middlewares({})", self.0.len())
+    }
+}
+
+/// Sends `request` through `middlewares` and then with `send`.
+pub(crate) fn send(
+    request: hyper::Request<hyper::Body>,
+    middlewares: &Arc<Middlewares>,
+    sensitive_headers: &Arc<SensitiveHeaders>,
+    send: SendRequest,
+) -> SendFuture {
+    let next = Next {
+        middlewares: middlewares.clone(),
+        index: 0,
+        send,
+        sensitive_headers: sensitive_headers.clone(),
+    };
+    Box::new(
+        next.run(request)
+            .map(Response::into_inner)
+            .map_err(failure::Error::from),
+    )
+}
@@ -42,64 +42,140 @@
 //!    println!("Contributors: {:?}", result);
 //!}
 
+use self::auth::Auth;
+use self::builder::HttperClientBuilder;
+use self::compress::RequestCompression;
+use self::connect::{encode_unix_url, HttpsConnector, ProxyConnector, UnixConnector};
+use self::cookie::CookieJar;
+use self::dns::DnsResolver;
+use self::error::{Error, ErrorKind};
 use self::get::Get;
+use self::middleware::Middlewares;
+use self::oauth2::OAuth2;
 use self::payload_builder::PayloadBuilder;
+use self::proxy::Proxy;
+use self::redact::SensitiveHeaders;
+use self::redirect::{RedirectChain, RedirectPolicy, SendFuture, SendRequest};
+use self::response::Response;
 use self::response_future::ResponseFuture;
-use failure::Error;
+use self::retry::RetryPolicy;
+use self::sigv4::SigV4;
+use self::timeout::{
+    from_hyper, from_timeout, TimeoutBody, TimeoutConnector, TimeoutError, Timeouts,
+    WithConnectTimeout,
+};
+use encoding_rs::Encoding;
+use failure;
 use futures::future;
 use http;
 use hyper::{self, rt::Future};
-use hyper_tls;
-use native_tls;
 use std::collections::HashMap;
 use std::default::Default;
-use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::timer::Timeout;
 
+pub mod auth;
+pub mod builder;
+pub mod compress;
+pub mod connect;
+pub mod cookie;
+mod decompress;
+pub mod dns;
+pub mod error;
 pub mod get;
+pub mod middleware;
+pub mod oauth2;
 pub mod payload_builder;
+pub mod proxy;
+mod redact;
+pub mod redirect;
+pub mod response;
 pub mod response_future;
+pub mod retry;
+pub mod sigv4;
+mod socks;
+pub mod timeout;
+pub mod tls;
+#[cfg(feature = "tracing")]
+mod trace;
 
 type HttpClient<C> = hyper::Client<C, hyper::Body>;
 type Url = str;
 
-pub type HttpsClient = HttpClient<hyper_tls::HttpsConnector<hyper::client::HttpConnector>>;
+pub type Connector = TimeoutConnector<
+    HttpsConnector<UnixConnector<ProxyConnector<hyper::client::HttpConnector<DnsResolver>>>>,
+>;
+pub type HttpsClient = HttpClient<Connector>;
 
 const PKG_VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 const PKG_NAME: Option<&'static str> = option_env!("CARGO_PKG_NAME");
 
 impl Default for HttperClient {
     fn default() -> Self {
-        let http_client: hyper::Client<
-            hyper_tls::HttpsConnector<hyper::client::HttpConnector>,
-            _,
-        > = build_https_client().expect("Failed to build HTTPs client");
-
-        let mut headers = HashMap::new();
-
-        let user_agent = hyper::header::USER_AGENT;
-
-        headers.insert(
-            user_agent.as_str().to_string(),
-            format!(
-                "{}/{}",
-                PKG_NAME.unwrap_or("unknown_name"),
-                PKG_VERSION.unwrap_or("unknown_version"),
-            ),
-        );
-
-        HttperClient {
-            http_client,
-            headers,
-        }
+        HttperClientBuilder::new()
+            .build()
+            .expect("Failed to build HTTPs client")
     }
 }
 
 pub type Headers = HashMap<String, String>;
 
-#[derive(Debug, Clone)]
+/// The settings of a request, overriding the client defaults when set.
+#[derive(Debug)]
+pub(crate) struct RequestOptions {
+    pub(crate) auth: Option<Auth>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) retry_policy: Option<RetryPolicy>,
+    pub(crate) compression: Option<RequestCompression>,
+    pub(crate) decompress: bool,
+}
+
+#[derive(Clone)]
 pub struct HttperClient {
     http_client: HttpsClient,
     headers: Headers,
+    timeouts: Timeouts,
+    proxies: Arc<Vec<Proxy>>,
+    redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
+    cookie_jar: Option<CookieJar>,
+    compression: RequestCompression,
+    auth: Option<Auth>,
+    oauth2: Option<OAuth2>,
+    // Fetches the `oauth2` tokens.
+    token_client: Option<Arc<HttperClient>>,
+    aws_sigv4: Option<SigV4>,
+    middlewares: Arc<Middlewares>,
+    sensitive_headers: Arc<SensitiveHeaders>,
+    default_charset: &'static Encoding,
+    max_body_size: Option<u64>,
+}
+
+impl fmt::Debug for HttperClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttperClient")
+            .field("http_client", &self.http_client)
+            .field(
+                "headers",
+                &self.sensitive_headers.redact_headers(&self.headers),
+            )
+            .field("timeouts", &self.timeouts)
+            .field("proxies", &self.proxies)
+            .field("redirect_policy", &self.redirect_policy)
+            .field("retry_policy", &self.retry_policy)
+            .field("cookie_jar", &self.cookie_jar)
+            .field("compression", &self.compression)
+            .field("auth", &self.auth)
+            .field("oauth2", &self.oauth2)
+            .field("aws_sigv4", &self.aws_sigv4)
+            .field("middlewares", &self.middlewares)
+            .field("sensitive_headers", &self.sensitive_headers)
+            .field("default_charset", &self.default_charset.name())
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
 }
 
 impl HttperClient {
@@ -118,6 +194,24 @@ impl HttperClient {
         }
     }
 
+    /// Creates a `HttperClientBuilder` to configure a `HttperClient`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httper::client::HttperClient;
+    ///
+    /// let httper_client = HttperClient::builder().dns_threads(2).build().unwrap();
+    /// ```
+    pub fn builder() -> HttperClientBuilder {
+        HttperClientBuilder::new()
+    }
+
+    /// Returns the `CookieJar` of the client, if it stores cookies.
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookie_jar.as_ref()
+    }
+
     /// Prepares a `GET` request to a given url `&str`.
     ///
     /// Call `.send()` to send the request.
@@ -131,8 +225,8 @@ impl HttperClient {
     ///
     /// httper_client.get("https://testing.local").send();
     /// ```
-    pub fn get(&self, url: &Url) -> Get {
-        Get::new(self.request_builder(url, hyper::Method::GET), &self)
+    pub fn get(&self, url: &Url) -> Get<'_> {
+        Get::new(self.request_builder(url, hyper::Method::GET), self)
     }
 
     /// Prepares a `POST` request to a given url `&str`.
@@ -147,8 +241,8 @@ impl HttperClient {
     ///
     /// httper_client.post("http://localhost:9090").payload("payload").send();
     /// ```
-    pub fn post(&self, url: &Url) -> PayloadBuilder {
-        PayloadBuilder::new(self.request_builder(url, hyper::Method::POST), &self)
+    pub fn post(&self, url: &Url) -> PayloadBuilder<'_> {
+        PayloadBuilder::new(self.request_builder(url, hyper::Method::POST), self)
     }
 
     /// Prepares a `DELETE` request to a given url `&str`.
@@ -163,8 +257,8 @@ impl HttperClient {
     ///
     /// httper_client.delete("http://localhost:9090").send();
     /// ```
-    pub fn delete(&self, url: &Url) -> PayloadBuilder {
-        PayloadBuilder::new(self.request_builder(url, hyper::Method::DELETE), &self)
+    pub fn delete(&self, url: &Url) -> PayloadBuilder<'_> {
+        PayloadBuilder::new(self.request_builder(url, hyper::Method::DELETE), self)
     }
 
     /// Prepares a `PUT` request to a given url `&str`.
@@ -179,8 +273,8 @@ impl HttperClient {
     ///
     /// httper_client.put("http://localhost:9090").payload("payload").send();
     /// ```
-    pub fn put(&self, url: &Url) -> PayloadBuilder {
-        PayloadBuilder::new(self.request_builder(url, hyper::Method::PUT), &self)
+    pub fn put(&self, url: &Url) -> PayloadBuilder<'_> {
+        PayloadBuilder::new(self.request_builder(url, hyper::Method::PUT), self)
     }
 
     /// Prepares a `PATCH` request to a given url `&str`.
@@ -195,8 +289,8 @@ impl HttperClient {
     ///
     /// httper_client.patch("http://localhost:9090").payload("payload").send();
     /// ```
-    pub fn patch(&self, url: &Url) -> PayloadBuilder {
-        PayloadBuilder::new(self.request_builder(url, hyper::Method::PATCH), &self)
+    pub fn patch(&self, url: &Url) -> PayloadBuilder<'_> {
+        PayloadBuilder::new(self.request_builder(url, hyper::Method::PATCH), self)
     }
 
     /// Get a `http::request::Builder` that will set the
@@ -222,6 +316,7 @@ impl HttperClient {
         request_builder: Result<http::request::Builder, Error>,
         payload: hyper::Body,
         headers: &Headers,
+        options: RequestOptions,
     ) -> ResponseFuture {
         // Make key lowercase so when we merge our default headers with the new ones
         // it will replace the default ones if a new matches it even if the casing
@@ -234,16 +329,216 @@ impl HttperClient {
         // as the default ones.
         let headers: Headers = self.headers.clone().into_iter().chain(headers).collect();
 
+        let timeouts = options.timeouts.or(self.timeouts);
+
         let http_client = self.http_client.clone();
+        // A request overriding the connect timeout passes it to the
+        // connector, keeping the connection pool of the client.
+        let connect_timeout = if timeouts.connect == self.timeouts.connect {
+            None
+        } else {
+            Some(timeouts.connect)
+        };
 
-        ResponseFuture(Box::new(
-            future::result(request_builder.and_then(|mut request_builder| {
-                headers.iter().for_each(|(k, v)| {
-                    request_builder.header(k.as_str(), v.as_str());
+        let redirect_policy = self.redirect_policy.clone();
+        let retry_policy = options
+            .retry_policy
+            .unwrap_or_else(|| self.retry_policy.clone());
+        let compression = options.compression.unwrap_or(self.compression);
+        let auth = options.auth.or_else(|| self.auth.clone());
+        let oauth2 = match (&auth, &self.oauth2, &self.token_client) {
+            (None, Some(oauth2), Some(token_client)) => {
+                Some((oauth2.clone(), token_client.clone()))
+            }
+            _ => None,
+        };
+        let decompress = options.decompress;
+        let aws_sigv4 = self.aws_sigv4.clone();
+        let middlewares = self.middlewares.clone();
+        let default_charset = self.default_charset;
+        let max_body_size = self.max_body_size;
+        let sensitive_headers = self.sensitive_headers.clone();
+        let response_sensitive_headers = self.sensitive_headers.clone();
+
+        let method = request_builder
+            .as_ref()
+            .ok()
+            .and_then(|request_builder| request_builder.method_ref().cloned());
+        let url = request_builder
+            .as_ref()
+            .ok()
+            .and_then(|request_builder| request_builder.uri_ref().cloned());
+
+        let request = request_builder.and_then(|mut request_builder| {
+            headers.iter().for_each(|(k, v)| {
+                request_builder.header(k.as_str(), v.as_str());
+            });
+            let mut request = request_builder
+                .body(payload)
+                .map_err(|error| Error::new(ErrorKind::Builder, error))?;
+            if let Some(ref auth) = auth {
+                auth.authorize(request.headers_mut())
+                    .map_err(|error| Error::new(ErrorKind::Builder, error))?;
+            }
+            Ok(request)
+        });
+
+        #[cfg(feature = "tracing")]
+        let trace = request
+            .as_ref()
+            .ok()
+            .map(|request| trace::RequestTrace::new(request, &self.sensitive_headers));
+        #[cfg(feature = "tracing")]
+        let send_trace = trace.clone();
+
+        let proxies = self.proxies.clone();
+        let cookie_jar = self.cookie_jar.clone();
+        #[cfg(feature = "tracing")]
+        let base_trace = trace.clone();
+        let send: SendRequest = Arc::new(move |mut request| -> SendFuture {
+            add_proxy_authorization(&mut request, &proxies);
+            let uri = request.uri().clone();
+            if let Some(ref cookie_jar) = cookie_jar {
+                cookie_jar.add_request_cookies(&uri, request.headers_mut());
+            }
+
+            // Requests are logged as they are sent, with their cookies
+            // and proxy credentials.
+            #[cfg(feature = "tracing")]
+            {
+                if let Some(ref trace) = base_trace {
+                    trace.sending(&request);
+                }
+            }
+
+            let response: SendFuture = match connect_timeout {
+                Some(timeout) => Box::new(
+                    WithConnectTimeout::new(http_client.request(request), timeout)
+                        .map_err(from_hyper),
+                ),
+                None => Box::new(http_client.request(request).map_err(from_hyper)),
+            };
+            let cookie_jar = match cookie_jar {
+                Some(ref cookie_jar) => cookie_jar.clone(),
+                None => return response,
+            };
+            Box::new(response.map(move |response| {
+                cookie_jar.store_response_cookies(&uri, response.headers());
+                response
+            }))
+        });
+
+        let response = future::result(request.map_err(failure::Error::from))
+            .and_then(move |request| compression.compress(request))
+            .and_then(move |mut request| {
+                // Requests are signed when they are sent, so retries
+                // and redirects are signed again.
+                let send: SendRequest = match aws_sigv4 {
+                    Some(signer) => {
+                        Arc::new(move |request| sigv4::send(request, &signer, send.clone()))
+                    }
+                    None => send,
+                };
+                let send: SendRequest = if middlewares.is_empty() {
+                    send
+                } else {
+                    let sensitive_headers = response_sensitive_headers.clone();
+                    Arc::new(move |request| {
+                        middleware::send(request, &middlewares, &sensitive_headers, send.clone())
+                    })
+                };
+                // Digest challenges are answered for each request sent, but
+                // only by the origin of the request.
+                let send: SendRequest = match auth {
+                    Some(auth @ Auth::Digest { .. }) => {
+                        let origin = request.uri().clone();
+                        Arc::new(move |request| auth::digest(request, &auth, &origin, send.clone()))
+                    }
+                    _ => send,
+                };
+                let send: SendRequest = match oauth2 {
+                    Some((oauth2, client)) => {
+                        let origin = request.uri().clone();
+                        Arc::new(move |request| {
+                            oauth2::send(request, &oauth2, &origin, &client, send.clone())
+                        })
+                    }
+                    None => send,
+                };
+                let send: SendRequest = Arc::new(move |request| {
+                    redirect::follow(request, redirect_policy.clone(), send.clone())
                 });
-                request_builder.body(payload).map_err(Error::from)
-            })).and_then(move |request| http_client.request(request).map_err(Error::from)),
-        ))
+
+                #[cfg(feature = "tracing")]
+                let send = match send_trace {
+                    Some(ref trace) => trace.attempts(send),
+                    None => send,
+                };
+
+                // Responses to `HEAD` requests have no body to decode.
+                let decode = decompress
+                    && decompress::accept_encoding(request.headers_mut())
+                    && request.method() != hyper::Method::HEAD;
+                let method = request.method().clone();
+                let url = request.uri().clone();
+                #[cfg(feature = "tracing")]
+                let body_trace = send_trace.clone();
+                retry::send(request, retry_policy, send).map(move |response| {
+                    let (method, url) = match response.extensions().get::<RedirectChain>() {
+                        Some(chain) => (
+                            chain.method().clone(),
+                            chain.uris().last().cloned().unwrap_or(url),
+                        ),
+                        None => (method, url),
+                    };
+                    #[cfg(feature = "tracing")]
+                    let response = match body_trace {
+                        Some(ref trace) => trace.count_body(response),
+                        None => response,
+                    };
+                    let response = if decode {
+                        Response::new(response, url).decode()
+                    } else {
+                        Response::new(response, url)
+                    };
+                    response
+                        .with_method(method)
+                        .with_default_charset(default_charset)
+                        .with_sensitive_headers(response_sensitive_headers)
+                        .max_body_size(max_body_size)
+                })
+            });
+
+        let deadline = timeouts.request.map(|timeout| Instant::now() + timeout);
+        let read_timeout = timeouts.read;
+
+        let response: Box<dyn Future<Item = _, Error = failure::Error> + Send> = match deadline {
+            Some(deadline) => Box::new(
+                Timeout::new_at(response, deadline)
+                    .map_err(|error| from_timeout(error, TimeoutError::Request)),
+            ),
+            None => Box::new(response),
+        };
+
+        let response: Box<dyn Future<Item = _, Error = failure::Error> + Send> =
+            Box::new(response.map(move |response| {
+                if deadline.is_none() && read_timeout.is_none() {
+                    return response;
+                }
+                response.map_body(|body| {
+                    hyper::Body::wrap_stream(TimeoutBody::new(body, deadline, read_timeout))
+                })
+            }));
+
+        #[cfg(feature = "tracing")]
+        let response: Box<dyn Future<Item = _, Error = failure::Error> + Send> = match trace {
+            Some(trace) => Box::new(trace.instrument(response)),
+            None => response,
+        };
+
+        ResponseFuture(Box::new(response.map_err(move |error| {
+            Error::from_failure(error).with_request(method, url, &sensitive_headers)
+        })))
     }
 
     /// Parses the url `&str` to a `hyper::Uri`.
@@ -251,24 +546,69 @@ impl HttperClient {
     /// # Errors
     /// Will return Err if the url couldn't be parsed into a `hyper::Uri`.
     fn parse_url(&self, url: &str) -> Result<hyper::Uri, Error> {
-        url.parse::<hyper::Uri>().map_err(Error::from)
+        encode_unix_url(url)
+            .map_err(|error| Error::new(ErrorKind::Builder, error))?
+            .parse::<hyper::Uri>()
+            .map_err(|error| Error::new(ErrorKind::Builder, error))
     }
 }
 
-/// Build a HTTPS client.
-/// Returns a Result that contains the client on success.
-fn build_https_client() -> Result<
-    hyper::client::Client<hyper_tls::HttpsConnector<hyper::client::HttpConnector>, hyper::Body>,
-    Box<error::Error>,
-> {
-    let tls_connector = native_tls::TlsConnector::builder().build()?;
+/// Adds the `Proxy-Authorization` header to `http` requests sent through
+/// a proxy, `https` requests authenticate when the tunnel is established.
+fn add_proxy_authorization(request: &mut hyper::Request<hyper::Body>, proxies: &[Proxy]) {
+    let uri = request.uri().clone();
+    if uri.scheme_part().map(|scheme| scheme.as_str()) != Some("http") {
+        return;
+    }
+
+    let authorization = proxies
+        .iter()
+        .find(|proxy| proxy.intercepts("http", uri.host().unwrap_or("")))
+        .and_then(|proxy| proxy.authorization());
 
-    let mut http_connector = hyper::client::HttpConnector::new(4);
+    if let Some(authorization) = authorization {
+        request
+            .headers_mut()
+            .entry(hyper::header::PROXY_AUTHORIZATION)
+            .expect("valid header name")
+            .or_insert_with(|| authorization.clone());
+    }
+}
+
+/// Build a HTTPS connector configured by the given `HttperClientBuilder`.
+/// Returns a Result that contains the connector on success.
+fn build_https_connector(
+    config: &HttperClientBuilder,
+    proxies: Arc<Vec<Proxy>>,
+) -> Result<Connector, failure::Error> {
+    let tls_connector = config.tls.build_tls_connector()?;
+
+    let resolver = config.dns.build_resolver(config.dns_threads);
+
+    let mut http_connector = hyper::client::HttpConnector::new_with_resolver(resolver.clone());
     http_connector.enforce_http(false);
+    http_connector.set_nodelay(config.tcp_nodelay);
+    http_connector.set_keepalive(config.tcp_keepalive);
 
-    let https_connector = hyper_tls::HttpsConnector::from((http_connector, tls_connector));
+    let proxy_connector = ProxyConnector::new(http_connector, proxies, resolver);
+    let unix_connector = UnixConnector::new(proxy_connector, config.unix_socket.clone());
+    let https_connector = HttpsConnector::new(unix_connector, tls_connector);
 
-    let client = hyper::client::Client::builder().build(https_connector);
+    Ok(TimeoutConnector::new(
+        https_connector,
+        config.timeouts.connect,
+    ))
+}
 
-    Ok(client)
+/// Build the `hyper::client::Builder` used to create clients
+/// on top of the connector.
+fn build_client_builder(config: &HttperClientBuilder) -> hyper::client::Builder {
+    let mut client_builder = hyper::client::Client::builder();
+    client_builder
+        .keep_alive_timeout(config.pool_idle_timeout)
+        .max_idle_per_host(config.pool_max_idle_per_host)
+        .http2_only(config.http2_prior_knowledge)
+        .http2_initial_stream_window_size(config.http2_initial_stream_window_size)
+        .http2_initial_connection_window_size(config.http2_initial_connection_window_size);
+    client_builder
 }
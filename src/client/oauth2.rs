@@ -0,0 +1,435 @@
+//! OAuth2 token management
+//!
+//! An `OAuth2` provider attached with `HttperClientBuilder::oauth2` fetches
+//! access tokens from a token endpoint, with the client credentials or the
+//! refresh token grant of RFC 6749, and sends them as bearer tokens with
+//! the requests of the client that don't set their own `Authorization`.
+//!
+//! Tokens are fetched with the client's connections, timeouts, proxies and
+//! maximum body size, but without its middleware, signing, cookies or
+//! retries, and cached until shortly before they expire. A `401 Unauthorized` response discards the token and
+//! the request is sent again with a new one, unless its body is streaming.
+//! Concurrent requests needing a token share a single token request.
+//!
+//! # Examples
+//!
+//! ```
+//! use httper::client::oauth2::OAuth2;
+//! use httper::client::HttperClient;
+//!
+//! let oauth2 = OAuth2::client_credentials("https://auth.testing.local/token", "id", "secret")
+//!     .scope("read")
+//!     .scope("write");
+//!
+//! let httper_client = HttperClient::builder().oauth2(oauth2).build().unwrap();
+//!
+//! httper_client.get("https://api.testing.local/resources").send();
+//! ```
+
+use client::auth::rebuild;
+use client::error::{Error as RequestError, ErrorKind};
+use client::redirect::{same_origin, SendFuture, SendRequest};
+use client::timeout::from_hyper;
+use client::HttperClient;
+use failure::Error;
+use futures::future::{self, Shared};
+use futures::{Future, Stream};
+use hyper::body::Payload;
+use hyper::header::{self, HeaderValue};
+use hyper::{self, StatusCode, Uri};
+use serde_json;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long before their expiry tokens are renewed.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+type TokenFuture = Box<dyn Future<Item = Token, Error = OAuth2Error> + Send>;
+
+/// Provides OAuth2 access tokens, shared by its clones.
+#[derive(Clone)]
+pub struct OAuth2 {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    refresh_token: Mutex<Option<String>>,
+    state: Mutex<State>,
+}
+
+// Cloned when the scopes of a provider shared with others change,
+// the tokens of the other scopes aren't kept.
+impl Clone for Inner {
+    fn clone(&self) -> Inner {
+        Inner {
+            token_url: self.token_url.clone(),
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+            scopes: self.scopes.clone(),
+            refresh_token: Mutex::new(self.refresh_token.lock().unwrap().clone()),
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    token: Option<Token>,
+    pending: Option<Shared<TokenFuture>>,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    access_token: String,
+    expires_at: Option<Instant>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl OAuth2 {
+    /// Fetches tokens from `token_url` with the client credentials grant.
+    pub fn client_credentials<U, I, S>(token_url: U, client_id: I, client_secret: S) -> OAuth2
+    where
+        U: Into<String>,
+        I: Into<String>,
+        S: Into<String>,
+    {
+        OAuth2::new(
+            token_url.into(),
+            client_id.into(),
+            client_secret.into(),
+            None,
+        )
+    }
+
+    /// Fetches tokens from `token_url` with the refresh token grant, starting
+    /// from `refresh_token`. Refresh tokens returned by the token endpoint
+    /// replace it.
+    pub fn refresh_token<U, I, S, R>(
+        token_url: U,
+        client_id: I,
+        client_secret: S,
+        refresh_token: R,
+    ) -> OAuth2
+    where
+        U: Into<String>,
+        I: Into<String>,
+        S: Into<String>,
+        R: Into<String>,
+    {
+        OAuth2::new(
+            token_url.into(),
+            client_id.into(),
+            client_secret.into(),
+            Some(refresh_token.into()),
+        )
+    }
+
+    fn new(
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        refresh_token: Option<String>,
+    ) -> OAuth2 {
+        OAuth2 {
+            inner: Arc::new(Inner {
+                token_url,
+                client_id,
+                client_secret,
+                scopes: Vec::new(),
+                refresh_token: Mutex::new(refresh_token),
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Adds a scope to request tokens for.
+    ///
+    /// A provider cloned before doesn't share its tokens with
+    /// the returned one anymore.
+    pub fn scope<S: Into<String>>(mut self, scope: S) -> OAuth2 {
+        Arc::make_mut(&mut self.inner).scopes.push(scope.into());
+        self
+    }
+
+    /// Returns the current refresh token, to be stored for later use.
+    pub fn current_refresh_token(&self) -> Option<String> {
+        self.inner.refresh_token.lock().unwrap().clone()
+    }
+
+    /// Returns a valid access token, fetching one with `client` when the
+    /// cached one expired. Concurrent calls share the same token request.
+    fn token(&self, client: &HttperClient) -> Shared<TokenFuture> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        if let Some(ref token) = state.token {
+            if token.expires_at.map_or(true, |expires_at| {
+                Instant::now() + EXPIRY_MARGIN < expires_at
+            }) {
+                let token: TokenFuture = Box::new(future::ok(token.clone()));
+                return token.shared();
+            }
+        }
+
+        if let Some(ref pending) = state.pending {
+            return pending.clone();
+        }
+
+        let inner = self.inner.clone();
+        let token: TokenFuture = Box::new(self.fetch(client).then(move |result| {
+            let mut state = inner.state.lock().unwrap();
+            state.pending = None;
+            if let Ok(ref token) = result {
+                state.token = Some(token.clone());
+            }
+            result
+        }));
+        let token = token.shared();
+        state.pending = Some(token.clone());
+        token
+    }
+
+    /// Discards `access_token` if it is the cached token.
+    fn invalidate(&self, access_token: &str) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state
+            .token
+            .as_ref()
+            .map_or(false, |token| token.access_token == access_token)
+        {
+            state.token = None;
+        }
+    }
+
+    /// Requests a token from the token endpoint.
+    fn fetch(&self, client: &HttperClient) -> impl Future<Item = Token, Error = OAuth2Error> {
+        let refresh_token = self.current_refresh_token();
+        let mut form = match refresh_token {
+            Some(ref refresh_token) => vec![
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.as_str()),
+            ],
+            None => vec![("grant_type", "client_credentials")],
+        };
+        let scope = self.inner.scopes.join(" ");
+        if !scope.is_empty() {
+            form.push(("scope", &scope));
+        }
+
+        let mut headers = std::collections::HashMap::new();
+        headers.insert(
+            header::CONTENT_TYPE.to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        );
+        headers.insert(header::ACCEPT.to_string(), "application/json".to_string());
+
+        let requested_at = Instant::now();
+        let inner = self.inner.clone();
+        client
+            .post(&self.inner.token_url)
+            .headers(headers)
+            .basic_auth(
+                form_urlencode(&self.inner.client_id),
+                form_urlencode(&self.inner.client_secret),
+            )
+            .payload(encode_form(&form))
+            .send()
+            .and_then(|response| response.bytes())
+            .map(|body| (body.status, body.value))
+            .map_err(|error| OAuth2Error::Request(Arc::new(error)))
+            .and_then(move |(status, body)| {
+                if !status.is_success() {
+                    let response = serde_json::from_slice::<ErrorResponse>(&body).ok();
+                    return Err(OAuth2Error::Rejected {
+                        status,
+                        error: response.as_ref().map(|response| response.error.clone()),
+                        description: response.and_then(|response| response.error_description),
+                    });
+                }
+
+                let response = serde_json::from_slice::<TokenResponse>(&body).map_err(|error| {
+                    OAuth2Error::Request(Arc::new(RequestError::new(ErrorKind::Decode, error)))
+                })?;
+                if let Some(refresh_token) = response.refresh_token {
+                    *inner.refresh_token.lock().unwrap() = Some(refresh_token);
+                }
+                Ok(Token {
+                    access_token: response.access_token,
+                    expires_at: response
+                        .expires_in
+                        .map(|expires_in| requested_at + Duration::from_secs(expires_in)),
+                })
+            })
+    }
+}
+
+impl fmt::Debug for OAuth2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OAuth2")
+            .field("token_url", &self.inner.token_url)
+            .field("client_id", &self.inner.client_id)
+            .field("client_secret", &"...")
+            .field("scopes", &self.inner.scopes)
+            .finish()
+    }
+}
+
+/// The error returned when an access token couldn't be fetched.
+///
+/// It is the `source` of the `Error` of requests that couldn't be
+/// authenticated, retrieve it with `error.source()` and
+/// `downcast_ref::<OAuth2Error>()`.
+#[derive(Debug, Clone)]
+pub enum OAuth2Error {
+    /// The token endpoint couldn't be reached or returned an invalid
+    /// response, shared by the requests waiting for the token.
+    Request(Arc<RequestError>),
+    /// The token endpoint rejected the token request.
+    Rejected {
+        status: StatusCode,
+        error: Option<String>,
+        description: Option<String>,
+    },
+}
+
+impl fmt::Display for OAuth2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OAuth2Error::Request(ref error) => write!(f, "token request failed: {}", error),
+            OAuth2Error::Rejected {
+                status,
+                ref error,
+                ref description,
+            } => {
+                write!(f, "token request rejected with {}", status)?;
+                if let Some(ref error) = *error {
+                    write!(f, ": {}", error)?;
+                }
+                if let Some(ref description) = *description {
+                    write!(f, " ({})", description)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl StdError for OAuth2Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            OAuth2Error::Request(ref error) => Some(&**error),
+            OAuth2Error::Rejected { .. } => None,
+        }
+    }
+}
+
+/// Sends `request` with `send`, authenticated with a token of `oauth2`
+/// fetched with `client`, and again with a new token when the response
+/// is `401 Unauthorized`. Tokens are only sent to `origin`.
+pub(crate) fn send(
+    request: hyper::Request<hyper::Body>,
+    oauth2: &OAuth2,
+    origin: &Uri,
+    client: &Arc<HttperClient>,
+    send: SendRequest,
+) -> SendFuture {
+    if !same_origin(request.uri(), origin) || request.headers().contains_key(header::AUTHORIZATION)
+    {
+        return send(request);
+    }
+
+    let token = token(oauth2, client);
+
+    // Streaming bodies can't be sent again, so their `401` response is returned.
+    if request.body().content_length().is_none() {
+        return Box::new(token.and_then(move |token| send_with(&send, request, &token)));
+    }
+
+    let oauth2 = oauth2.clone();
+    let client = client.clone();
+    let (parts, body) = request.into_parts();
+    Box::new(
+        body.concat2()
+            .map_err(from_hyper)
+            .join(token)
+            .and_then(move |(body, token)| {
+                let body = body.to_vec();
+                send_with(&send, rebuild(&parts, body.clone()), &token)
+                    .map(move |response| (response, parts, body, token, send))
+            })
+            .and_then(move |(response, parts, body, token, send)| -> SendFuture {
+                if response.status() != StatusCode::UNAUTHORIZED {
+                    return Box::new(future::ok(response));
+                }
+
+                oauth2.invalidate(&token.access_token);
+                Box::new(
+                    self::token(&oauth2, &client)
+                        .and_then(move |token| send_with(&send, rebuild(&parts, body), &token)),
+                )
+            }),
+    )
+}
+
+fn token(oauth2: &OAuth2, client: &HttperClient) -> impl Future<Item = Token, Error = Error> {
+    oauth2
+        .token(client)
+        .map(|token| (*token).clone())
+        .map_err(|error| Error::from((*error).clone()))
+}
+
+/// Sends `request` with `send` and `token` as its bearer token.
+fn send_with(
+    send: &SendRequest,
+    mut request: hyper::Request<hyper::Body>,
+    token: &Token,
+) -> SendFuture {
+    match HeaderValue::from_str(&format!("Bearer {}", token.access_token)) {
+        Ok(mut authorization) => {
+            authorization.set_sensitive(true);
+            request
+                .headers_mut()
+                .insert(header::AUTHORIZATION, authorization);
+            send(request)
+        }
+        Err(error) => Box::new(future::err(Error::from(error))),
+    }
+}
+
+/// Percent-encodes `value` for `application/x-www-form-urlencoded` bodies.
+fn form_urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                (byte as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn encode_form(form: &[(&str, &str)]) -> String {
+    form.iter()
+        .map(|(name, value)| format!("{}={}", form_urlencode(name), form_urlencode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
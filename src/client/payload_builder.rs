@@ -1,16 +1,49 @@
-use super::{Headers, HttperClient};
+use super::{Headers, HttperClient, RequestOptions};
+use client::auth::Auth;
+use client::compress::RequestCompression;
+use client::error::Error;
 use client::response_future::ResponseFuture;
-use failure::Error;
+use client::retry::RetryPolicy;
+use client::timeout::Timeouts;
 use http;
 use hyper;
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
-#[derive(Debug)]
 pub struct PayloadBuilder<'a> {
     pub request_builder: Result<http::request::Builder, Error>,
     pub client: &'a HttperClient,
     pub payload: Option<hyper::Body>,
     pub headers: Headers,
+    pub timeouts: Timeouts,
+    pub retry_policy: Option<RetryPolicy>,
+    pub decompress: bool,
+    pub auth: Option<Auth>,
+    pub compression: Option<RequestCompression>,
+}
+
+impl<'a> fmt::Debug for PayloadBuilder<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sensitive = &self.client.sensitive_headers;
+        f.debug_struct("PayloadBuilder")
+            .field(
+                "request_builder",
+                &self
+                    .request_builder
+                    .as_ref()
+                    .map(|builder| sensitive.redact_builder(builder)),
+            )
+            .field("client", &self.client)
+            .field("payload", &self.payload)
+            .field("headers", &sensitive.redact_headers(&self.headers))
+            .field("timeouts", &self.timeouts)
+            .field("retry_policy", &self.retry_policy)
+            .field("decompress", &self.decompress)
+            .field("auth", &self.auth)
+            .field("compression", &self.compression)
+            .finish()
+    }
 }
 
 impl<'a> PayloadBuilder<'a> {
@@ -25,6 +58,11 @@ impl<'a> PayloadBuilder<'a> {
             client,
             payload: None,
             headers: HashMap::new(),
+            timeouts: Timeouts::default(),
+            retry_policy: None,
+            decompress: true,
+            auth: None,
+            compression: None,
         }
     }
 
@@ -47,12 +85,7 @@ impl<'a> PayloadBuilder<'a> {
     ///     .send();
     /// ```
     pub fn headers(self, headers: Headers) -> Self {
-        PayloadBuilder {
-            headers,
-            request_builder: self.request_builder,
-            client: self.client,
-            payload: self.payload,
-        }
+        PayloadBuilder { headers, ..self }
     }
 
     /// Attaches payload to the request.
@@ -62,10 +95,111 @@ impl<'a> PayloadBuilder<'a> {
         hyper::Body: From<P>,
     {
         PayloadBuilder {
-            request_builder: self.request_builder,
-            client: self.client,
             payload: Some(hyper::Body::from(payload)),
-            headers: self.headers,
+            ..self
+        }
+    }
+
+    /// Sets the time allowed to establish a connection for this request,
+    /// overriding the client default.
+    ///
+    /// Exceeding it fails the request with `TimeoutError::Connect`.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        PayloadBuilder {
+            timeouts: Timeouts {
+                connect: Some(timeout),
+                ..self.timeouts
+            },
+            ..self
+        }
+    }
+
+    /// Sets the total time allowed for this request, overriding
+    /// the client default.
+    ///
+    /// Exceeding it fails the request with `TimeoutError::Request`.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        PayloadBuilder {
+            timeouts: Timeouts {
+                request: Some(timeout),
+                ..self.timeouts
+            },
+            ..self
+        }
+    }
+
+    /// Sets the time allowed to pass between two reads of the
+    /// response body for this request, overriding the client default.
+    ///
+    /// Exceeding it fails the read with `TimeoutError::Read`.
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        PayloadBuilder {
+            timeouts: Timeouts {
+                read: Some(timeout),
+                ..self.timeouts
+            },
+            ..self
+        }
+    }
+
+    /// Sets the policy deciding whether this request is sent
+    /// again when it fails, overriding the client default.
+    pub fn retry(self, retry_policy: RetryPolicy) -> Self {
+        PayloadBuilder {
+            retry_policy: Some(retry_policy),
+            ..self
+        }
+    }
+
+    /// Controls whether a response compressed with an encoding enabled
+    /// by the `gzip`, `deflate`, `brotli` or `zstd` features is decoded.
+    /// Defaults to `true`.
+    ///
+    /// When disabled, `Accept-Encoding` isn't added to the request
+    /// and the response body is returned as received.
+    pub fn decompress(self, decompress: bool) -> Self {
+        PayloadBuilder { decompress, ..self }
+    }
+
+    /// Sets how the payload is compressed, overriding the client
+    /// default, see the `compress` module.
+    pub fn compress(self, compression: RequestCompression) -> Self {
+        PayloadBuilder {
+            compression: Some(compression),
+            ..self
+        }
+    }
+
+    /// Authenticates the request with HTTP Basic authentication,
+    /// overriding the client default, see the `auth` module.
+    pub fn basic_auth<U: Into<String>, P: Into<String>>(self, username: U, password: P) -> Self {
+        PayloadBuilder {
+            auth: Some(Auth::Basic {
+                username: username.into(),
+                password: password.into(),
+            }),
+            ..self
+        }
+    }
+
+    /// Authenticates the request with a bearer token,
+    /// overriding the client default, see the `auth` module.
+    pub fn bearer_auth<T: Into<String>>(self, token: T) -> Self {
+        PayloadBuilder {
+            auth: Some(Auth::Bearer(token.into())),
+            ..self
+        }
+    }
+
+    /// Authenticates the request with HTTP Digest authentication,
+    /// overriding the client default, see the `auth` module.
+    pub fn digest_auth<U: Into<String>, P: Into<String>>(self, username: U, password: P) -> Self {
+        PayloadBuilder {
+            auth: Some(Auth::Digest {
+                username: username.into(),
+                password: password.into(),
+            }),
+            ..self
         }
     }
 
@@ -74,8 +208,15 @@ impl<'a> PayloadBuilder<'a> {
     pub fn send(self) -> ResponseFuture {
         self.client.send_request(
             self.request_builder,
-            self.payload.unwrap_or_else(|| hyper::Body::empty()),
+            self.payload.unwrap_or_else(hyper::Body::empty),
             &self.headers,
+            RequestOptions {
+                auth: self.auth,
+                timeouts: self.timeouts,
+                retry_policy: self.retry_policy,
+                compression: self.compression,
+                decompress: self.decompress,
+            },
         )
     }
 }
@@ -0,0 +1,207 @@
+//! Masking of sensitive header values and url query parameters.
+
+use super::Headers;
+use http;
+use hyper::header::HeaderMap;
+use hyper::Uri;
+use std::fmt;
+
+/// The query parameters of presigned urls whose values are masked.
+const SENSITIVE_PARAMS: &[&str] = &[
+    "x-amz-credential",
+    "x-amz-security-token",
+    "x-amz-signature",
+];
+
+/// Formats `uri` with the password of its user info and the values of the
+/// credential, security token and signature query parameters of presigned
+/// urls masked.
+pub(crate) fn redact_uri(uri: &Uri) -> RedactedUri<'_> {
+    RedactedUri(uri)
+}
+
+/// The headers whose values are masked, `Authorization`, `Proxy-Authorization`,
+/// `Cookie` and `Set-Cookie` by default.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SensitiveHeaders(Vec<String>);
+
+impl SensitiveHeaders {
+    /// Marks the header `name` as sensitive.
+    pub(crate) fn add(&mut self, name: &str) {
+        if !self.contains(name) {
+            self.0.push(name.to_lowercase());
+        }
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.0
+            .iter()
+            .any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+    }
+
+    /// Formats `headers` with the values of sensitive headers masked,
+    /// including values marked sensitive with `HeaderValue::set_sensitive`.
+    pub(crate) fn redact<'a>(&'a self, headers: &'a HeaderMap) -> RedactedHeaderMap<'a> {
+        RedactedHeaderMap {
+            headers,
+            sensitive: self,
+        }
+    }
+
+    /// Formats `headers` with the values of sensitive headers masked.
+    pub(crate) fn redact_headers<'a>(&'a self, headers: &'a Headers) -> RedactedHeaders<'a> {
+        RedactedHeaders {
+            headers,
+            sensitive: self,
+        }
+    }
+
+    /// Formats the method, uri and headers of `builder`, with the
+    /// values of sensitive headers masked.
+    pub(crate) fn redact_builder<'a>(
+        &'a self,
+        builder: &'a http::request::Builder,
+    ) -> RedactedBuilder<'a> {
+        RedactedBuilder {
+            builder,
+            sensitive: self,
+        }
+    }
+}
+
+impl Default for SensitiveHeaders {
+    fn default() -> SensitiveHeaders {
+        SensitiveHeaders(
+            [
+                "authorization",
+                "proxy-authorization",
+                "cookie",
+                "set-cookie",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        )
+    }
+}
+
+/// The placeholder replacing sensitive values.
+pub(crate) struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("[redacted]")
+    }
+}
+
+/// A `HeaderMap` formatted with sensitive values masked.
+pub(crate) struct RedactedHeaderMap<'a> {
+    headers: &'a HeaderMap,
+    sensitive: &'a SensitiveHeaders,
+}
+
+impl<'a> fmt::Debug for RedactedHeaderMap<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.headers.iter().map(|(name, value)| {
+                let value: &dyn fmt::Debug =
+                    if value.is_sensitive() || self.sensitive.contains(name.as_str()) {
+                        &Redacted
+                    } else {
+                        value
+                    };
+                (name, value)
+            }))
+            .finish()
+    }
+}
+
+/// `Headers` formatted with sensitive values masked.
+pub(crate) struct RedactedHeaders<'a> {
+    headers: &'a Headers,
+    sensitive: &'a SensitiveHeaders,
+}
+
+impl<'a> fmt::Debug for RedactedHeaders<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.headers.iter().map(|(name, value)| {
+                let value: &dyn fmt::Debug = if self.sensitive.contains(name) {
+                    &Redacted
+                } else {
+                    value
+                };
+                (name, value)
+            }))
+            .finish()
+    }
+}
+
+/// A `Uri` formatted with its password and sensitive query parameters masked.
+pub(crate) struct RedactedUri<'a>(&'a Uri);
+
+impl<'a> fmt::Display for RedactedUri<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let uri = self.0;
+        if let Some(scheme) = uri.scheme_part() {
+            write!(f, "{}://", scheme)?;
+        }
+        if let Some(authority) = uri.authority_part() {
+            let authority = authority.as_str();
+            match authority.rfind('@') {
+                Some(at) => {
+                    let username = authority[..at].split(':').next().unwrap_or("");
+                    write!(f, "{}:{:?}{}", username, Redacted, &authority[at..])?;
+                }
+                None => f.write_str(authority)?,
+            }
+        }
+        f.write_str(uri.path())?;
+
+        let query = match uri.query() {
+            Some(query) => query,
+            None => return Ok(()),
+        };
+        for (index, param) in query.split('&').enumerate() {
+            f.write_str(if index == 0 { "?" } else { "&" })?;
+            let name = param.split('=').next().unwrap_or("");
+            if SENSITIVE_PARAMS
+                .iter()
+                .any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+            {
+                write!(f, "{}={:?}", name, Redacted)?;
+            } else {
+                f.write_str(param)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for RedactedUri<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A request builder formatted with sensitive header values masked.
+pub(crate) struct RedactedBuilder<'a> {
+    builder: &'a http::request::Builder,
+    sensitive: &'a SensitiveHeaders,
+}
+
+impl<'a> fmt::Debug for RedactedBuilder<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("method", &self.builder.method_ref())
+            .field("uri", &self.builder.uri_ref())
+            .field(
+                "headers",
+                &self
+                    .builder
+                    .headers_ref()
+                    .map(|headers| self.sensitive.redact(headers)),
+            )
+            .finish()
+    }
+}
@@ -0,0 +1,412 @@
+//! Redirect policies
+//!
+//! By default a `HttperClient` follows up to 10 redirects. The policy is
+//! set with `HttperClientBuilder::redirect`.
+//!
+//! `301` and `302` redirects of `POST` requests and `303` redirects of
+//! any request but `HEAD` are followed with a `GET` without body, while
+//! `307` and `308` redirects repeat the request. Streaming bodies can't be
+//! sent twice, so `307` and `308` redirects of requests with one are
+//! returned as is. The `Authorization` and `Cookie` headers are removed
+//! when a redirect leads to another origin.
+//!
+//! The urls requested are recorded in the `RedirectChain` extension
+//! of the final response.
+
+use client::error::{Error as RequestError, ErrorKind};
+use client::timeout::from_hyper;
+use failure::Error;
+use futures::future::{self, Either, Loop};
+use futures::{Future, Stream};
+use http;
+use hyper::body::Payload;
+use hyper::header::{self, HeaderMap};
+use hyper::{self, Method, StatusCode, Uri};
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+use url::Url;
+
+/// Decides whether a redirect is followed.
+///
+/// # Examples
+///
+/// ```
+/// use httper::client::redirect::{RedirectAction, RedirectPolicy};
+/// use httper::client::HttperClient;
+///
+/// let httper_client = HttperClient::builder()
+///     .redirect(RedirectPolicy::custom(|attempt| {
+///         if attempt.next().host() == Some("internal.example.com") {
+///             RedirectAction::Stop
+///         } else if attempt.previous().len() > 5 {
+///             RedirectAction::Error("too many redirects".into())
+///         } else {
+///             RedirectAction::Follow
+///         }
+///     }))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct RedirectPolicy(Policy);
+
+#[derive(Clone)]
+enum Policy {
+    None,
+    Limited(usize),
+    Custom(Arc<dyn Fn(&RedirectAttempt<'_>) -> RedirectAction + Send + Sync>),
+}
+
+impl RedirectPolicy {
+    /// Doesn't follow any redirect.
+    pub fn none() -> RedirectPolicy {
+        RedirectPolicy(Policy::None)
+    }
+
+    /// Follows up to `max` redirects, failing the request with
+    /// `RedirectError::TooManyRedirects` after that.
+    pub fn limited(max: usize) -> RedirectPolicy {
+        RedirectPolicy(Policy::Limited(max))
+    }
+
+    /// Decides with `policy` whether each redirect is followed.
+    pub fn custom<F>(policy: F) -> RedirectPolicy
+    where
+        F: Fn(&RedirectAttempt<'_>) -> RedirectAction + Send + Sync + 'static,
+    {
+        RedirectPolicy(Policy::Custom(Arc::new(policy)))
+    }
+
+    fn redirect(&self, attempt: &RedirectAttempt<'_>) -> RedirectAction {
+        match self.0 {
+            Policy::None => RedirectAction::Stop,
+            Policy::Limited(max) if attempt.previous.len() > max => {
+                RedirectAction::Error(Box::new(RedirectError::TooManyRedirects(max)))
+            }
+            Policy::Limited(_) => RedirectAction::Follow,
+            Policy::Custom(ref policy) => policy(attempt),
+        }
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy::limited(10)
+    }
+}
+
+impl fmt::Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Policy::None => f.pad("RedirectPolicy::none()"),
+            Policy::Limited(max) => write!(f, "RedirectPolicy::limited({})", max),
+            Policy::Custom(..) => f.pad("RedirectPolicy::custom(..)"),
+        }
+    }
+}
+
+/// A redirect about to be followed, passed to custom policies.
+#[derive(Debug)]
+pub struct RedirectAttempt<'a> {
+    status: StatusCode,
+    next: &'a Uri,
+    previous: &'a [Uri],
+}
+
+impl<'a> RedirectAttempt<'a> {
+    /// Returns the status of the redirect response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the url the response redirects to.
+    pub fn next(&self) -> &Uri {
+        self.next
+    }
+
+    /// Returns the urls requested so far, the last one
+    /// being the url of the redirect response.
+    pub fn previous(&self) -> &[Uri] {
+        self.previous
+    }
+}
+
+/// What to do with a redirect.
+#[derive(Debug)]
+pub enum RedirectAction {
+    /// Follow the redirect.
+    Follow,
+    /// Return the redirect response.
+    Stop,
+    /// Fail the request with an `ErrorKind::Redirect` error
+    /// whose source is this error.
+    Error(Box<dyn StdError + Send + Sync>),
+}
+
+/// The error returned when a redirect couldn't be followed.
+///
+/// It is the `source` of the `Error` of requests whose redirects couldn't
+/// be followed, retrieve it with `error.source()` and
+/// `downcast_ref::<RedirectError>()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectError {
+    /// More redirects than allowed by the policy were returned.
+    TooManyRedirects(usize),
+    /// A redirect led back to an url requested before with the same method.
+    Loop(Uri),
+    /// The `Location` of a redirect isn't a valid url.
+    InvalidLocation(String),
+}
+
+impl fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RedirectError::TooManyRedirects(max) => {
+                write!(f, "too many redirects, at most {} are allowed", max)
+            }
+            RedirectError::Loop(ref uri) => write!(f, "redirect loop at {}", uri),
+            RedirectError::InvalidLocation(ref location) => {
+                write!(f, "invalid redirect location: {}", location)
+            }
+        }
+    }
+}
+
+impl StdError for RedirectError {}
+
+/// The urls requested to get a response, in order, the last
+/// one being the url of the response.
+///
+/// Stored in the extensions of responses, retrieve it with
+/// `response.extensions().get::<RedirectChain>()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectChain {
+    uris: Vec<Uri>,
+    method: Method,
+}
+
+impl RedirectChain {
+    /// Returns the urls requested.
+    pub fn uris(&self) -> &[Uri] {
+        &self.uris
+    }
+
+    /// Returns the method of the last request, which is `GET`
+    /// after a `303` or a `POST` followed on a `301` or `302`.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+}
+
+pub(crate) type SendFuture =
+    Box<dyn Future<Item = hyper::Response<hyper::Body>, Error = Error> + Send>;
+
+/// Sends a single request, without following redirects.
+pub(crate) type SendRequest = Arc<dyn Fn(hyper::Request<hyper::Body>) -> SendFuture + Send + Sync>;
+
+/// The body of a request, kept around to be sent again on redirects
+/// when it is in memory.
+enum Body {
+    Full(Vec<u8>),
+    Streaming(Option<hyper::Body>),
+}
+
+impl Body {
+    fn take(&mut self) -> Option<hyper::Body> {
+        match *self {
+            Body::Full(ref bytes) => Some(hyper::Body::from(bytes.clone())),
+            Body::Streaming(ref mut body) => body.take(),
+        }
+    }
+}
+
+struct State {
+    parts: http::request::Parts,
+    body: Body,
+    chain: Vec<Uri>,
+    // The requests sent so far, a url can be requested again
+    // with another method, like a `GET` after a `POST`.
+    requested: Vec<(Method, Uri)>,
+}
+
+/// Sends `request` with `send`, following redirects as allowed by `policy`.
+pub(crate) fn follow(
+    request: hyper::Request<hyper::Body>,
+    policy: RedirectPolicy,
+    send: SendRequest,
+) -> SendFuture {
+    if let Policy::None = policy.0 {
+        return send(request);
+    }
+
+    let (parts, body) = request.into_parts();
+
+    // Bodies in memory have a known length and are collected right away.
+    let body = if body.content_length().is_some() {
+        Either::A(
+            body.concat2()
+                .map(|chunk| Body::Full(chunk.to_vec()))
+                .map_err(from_hyper),
+        )
+    } else {
+        Either::B(future::ok(Body::Streaming(Some(body))))
+    };
+
+    Box::new(body.and_then(move |body| {
+        let state = State {
+            parts,
+            body,
+            chain: Vec::new(),
+            requested: Vec::new(),
+        };
+
+        future::loop_fn(state, move |mut state| {
+            let policy = policy.clone();
+
+            let body = state.body.take().unwrap_or_else(hyper::Body::empty);
+            let mut request = hyper::Request::new(body);
+            *request.method_mut() = state.parts.method.clone();
+            *request.uri_mut() = state.parts.uri.clone();
+            *request.version_mut() = state.parts.version;
+            *request.headers_mut() = state.parts.headers.clone();
+
+            state.chain.push(state.parts.uri.clone());
+            state
+                .requested
+                .push((state.parts.method.clone(), state.parts.uri.clone()));
+
+            send(request).and_then(move |response| {
+                let next = match next_request(&response, &mut state, &policy)? {
+                    Some(next) => next,
+                    None => {
+                        let mut response = response;
+                        response.extensions_mut().insert(RedirectChain {
+                            uris: state.chain,
+                            method: state.parts.method,
+                        });
+                        return Ok(Loop::Break(response));
+                    }
+                };
+
+                state.parts.uri = next;
+                Ok(Loop::Continue(state))
+            })
+        })
+    }))
+}
+
+/// Prepares `state` for following the redirect `response` and returns
+/// the url to request next, or `None` if `response` should be returned.
+fn next_request(
+    response: &hyper::Response<hyper::Body>,
+    state: &mut State,
+    policy: &RedirectPolicy,
+) -> Result<Option<Uri>, Error> {
+    let status = response.status();
+    match status {
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => {}
+        _ => return Ok(None),
+    }
+
+    let location = match response.headers().get(header::LOCATION) {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    let next = location
+        .to_str()
+        .ok()
+        .and_then(|location| resolve(&state.parts.uri, location))
+        .ok_or_else(|| {
+            RedirectError::InvalidLocation(String::from_utf8_lossy(location.as_bytes()).into())
+        })?;
+
+    let becomes_get = match status {
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => state.parts.method == Method::POST,
+        StatusCode::SEE_OTHER => state.parts.method != Method::HEAD,
+        _ => false,
+    };
+
+    // A streaming body has already been sent and can't be repeated.
+    if let Body::Streaming(None) = state.body {
+        if !becomes_get {
+            return Ok(None);
+        }
+    }
+
+    let attempt = RedirectAttempt {
+        status,
+        next: &next,
+        previous: &state.chain,
+    };
+    match policy.redirect(&attempt) {
+        RedirectAction::Follow => {}
+        RedirectAction::Stop => return Ok(None),
+        RedirectAction::Error(error) => {
+            return Err(Error::from(RequestError::new(ErrorKind::Redirect, error)))
+        }
+    }
+
+    let method = if becomes_get {
+        Method::GET
+    } else {
+        state.parts.method.clone()
+    };
+    if state
+        .requested
+        .iter()
+        .any(|requested| requested.0 == method && requested.1 == next)
+    {
+        return Err(Error::from(RedirectError::Loop(next)));
+    }
+
+    if becomes_get {
+        state.parts.method = Method::GET;
+        state.body = Body::Full(Vec::new());
+        remove_content_headers(&mut state.parts.headers);
+    }
+
+    if !same_origin(&state.parts.uri, &next) {
+        state.parts.headers.remove(header::AUTHORIZATION);
+        state.parts.headers.remove(header::COOKIE);
+        state.parts.headers.remove(header::PROXY_AUTHORIZATION);
+    }
+
+    Ok(Some(next))
+}
+
+fn remove_content_headers(headers: &mut HeaderMap) {
+    for name in &[
+        header::CONTENT_LENGTH,
+        header::CONTENT_TYPE,
+        header::CONTENT_ENCODING,
+        header::TRANSFER_ENCODING,
+    ] {
+        headers.remove(name);
+    }
+}
+
+pub(crate) fn same_origin(a: &Uri, b: &Uri) -> bool {
+    a.scheme_part() == b.scheme_part() && a.host() == b.host() && port(a) == port(b)
+}
+
+/// Returns the port of `uri`, or the default port of its scheme.
+fn port(uri: &Uri) -> Option<u16> {
+    uri.port_u16().or_else(|| match uri.scheme_str() {
+        Some("http") => Some(80),
+        Some("https") => Some(443),
+        _ => None,
+    })
+}
+
+/// Resolves the `location` of a redirect against the url `base`.
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    let mut url = Url::parse(&base.to_string()).ok()?.join(location).ok()?;
+    // Fragments aren't sent to servers.
+    url.set_fragment(None);
+    url.as_str().parse().ok()
+}
@@ -0,0 +1,420 @@
+//! Responses
+//!
+//! `ResponseFuture` resolves to a `Response` once the status and the
+//! headers of the response have been received. Its body can then be
+//! streamed with `into_body`, or read whole with `json`, `text` or `bytes`,
+//! which resolve to a `Decoded` value keeping the status, the headers, the
+//! version and the url of the response alongside the decoded body.
+//!
+//! `text` decodes the body with the `charset` parameter of the
+//! `Content-Type` header. Without one, the encoding of a byte order mark
+//! (BOM) at the start of the body is used, and otherwise the client's
+//! default charset, UTF-8 unless set with
+//! `HttperClientBuilder::default_charset`. `text_with_charset` decodes
+//! the body with a given charset instead.
+//!
+//! Bodies read whole can be limited to a maximum size, set for every
+//! response with `HttperClientBuilder::max_body_size` or for a single
+//! one with `max_body_size`. A response announcing a larger
+//! `Content-Length` fails right away, and one streaming more bytes than
+//! allowed fails as soon as they are received, with an error for which
+//! `Error::is_body_too_large` is true. Compressed bodies are limited
+//! while they are decompressed, so this also applies to their
+//! decompressed bytes streamed with `into_body`.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! extern crate httper;
+//! #[macro_use]
+//! extern crate serde_derive;
+//!
+//! use futures::Future;
+//! use httper::client::HttperClient;
+//!
+//! fn main() {
+//!     #[derive(Debug, Deserialize)]
+//!     struct Data {
+//!         name: String,
+//!     }
+//!
+//!     let httper_client = HttperClient::new();
+//!
+//!     httper_client
+//!         .get("https://testing.local")
+//!         .send()
+//!         .and_then(|response| response.json::<Data>())
+//!         .map(|data| {
+//!             println!("ETag: {:?}", data.headers.get("etag"));
+//!             println!("Name: {}", data.value.name);
+//!         });
+//! }
+//! ```
+
+use client::decompress::{self, Limit};
+use client::error::{Error, ErrorKind};
+use client::redact::{redact_uri, SensitiveHeaders};
+use encoding_rs::{Encoding, UTF_8};
+use futures::future::{self, Either};
+use futures::{Future, Stream};
+use http;
+use hyper::header::{self, HeaderMap};
+use hyper::{self, Method, StatusCode, Uri, Version};
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::fmt;
+use std::sync::Arc;
+
+/// A response, see the module documentation.
+pub struct Response {
+    inner: hyper::Response<hyper::Body>,
+    url: Uri,
+    method: Option<Method>,
+    default_charset: &'static Encoding,
+    max_body_size: Option<u64>,
+    // The limit of the decompressed body, if it is decoded.
+    decode_limit: Option<Limit>,
+    sensitive_headers: Arc<SensitiveHeaders>,
+}
+
+impl Response {
+    /// Creates a `Response` from a `hyper::Response` to a request to `url`,
+    /// for middleware answering requests themselves.
+    pub fn new(response: hyper::Response<hyper::Body>, url: Uri) -> Response {
+        Response {
+            inner: response,
+            url,
+            method: None,
+            default_charset: UTF_8,
+            max_body_size: None,
+            decode_limit: None,
+            sensitive_headers: Arc::new(SensitiveHeaders::default()),
+        }
+    }
+
+    /// Decodes the body if it is compressed, see `decompress::decode`.
+    pub(crate) fn decode(self) -> Response {
+        let limit = Limit::new(self.max_body_size);
+        Response {
+            inner: decompress::decode(self.inner, &limit),
+            decode_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Sets the sensitive headers of the client, masked in the `Debug`
+    /// output of the response and of its errors.
+    pub(crate) fn with_sensitive_headers(
+        self,
+        sensitive_headers: Arc<SensitiveHeaders>,
+    ) -> Response {
+        Response {
+            sensitive_headers,
+            ..self
+        }
+    }
+
+    /// Sets the method of the request, kept in the errors of the response.
+    pub(crate) fn with_method(self, method: Method) -> Response {
+        Response {
+            method: Some(method),
+            ..self
+        }
+    }
+
+    /// Sets the charset `text` falls back to.
+    pub(crate) fn with_default_charset(self, default_charset: &'static Encoding) -> Response {
+        Response {
+            default_charset,
+            ..self
+        }
+    }
+
+    /// Sets the maximum size in bytes of the body read by `json`, `text`
+    /// and `bytes`, see the module documentation.
+    ///
+    /// Pass `None` to read bodies of any size. Defaults to the client's
+    /// maximum body size.
+    pub fn max_body_size<L: Into<Option<u64>>>(self, limit: L) -> Response {
+        let max_body_size = limit.into();
+        if let Some(ref limit) = self.decode_limit {
+            limit.set(max_body_size);
+        }
+        Response {
+            max_body_size,
+            ..self
+        }
+    }
+
+    /// Returns the status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.inner.status()
+    }
+
+    /// Returns the headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    /// Returns a mutable reference to the headers of the response.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.inner.headers_mut()
+    }
+
+    /// Returns the HTTP version of the response.
+    pub fn version(&self) -> Version {
+        self.inner.version()
+    }
+
+    /// Returns the url of the response, the last one requested
+    /// when redirects were followed.
+    pub fn url(&self) -> &Uri {
+        &self.url
+    }
+
+    /// Returns the extensions of the response, like the `RedirectChain`.
+    pub fn extensions(&self) -> &http::Extensions {
+        self.inner.extensions()
+    }
+
+    /// Returns a mutable reference to the extensions of the response.
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        self.inner.extensions_mut()
+    }
+
+    /// Returns the body of the response, to be streamed.
+    pub fn into_body(self) -> hyper::Body {
+        self.inner.into_body()
+    }
+
+    /// Returns the `hyper::Response` wrapped by this response.
+    pub fn into_inner(self) -> hyper::Response<hyper::Body> {
+        self.inner
+    }
+
+    /// Deserialize the json body into a `T`.
+    ///
+    /// # Errors
+    /// Will return Err if the body couldn't be read or exceeds the maximum
+    /// body size, or if it couldn't be deserialized into a `T`, in which
+    /// case `Error::is_decode` is true.
+    pub fn json<T: DeserializeOwned>(self) -> impl Future<Item = Decoded<T>, Error = Error> {
+        self.bytes().and_then(|bytes| {
+            let value = serde_json::from_slice(&bytes.value).map_err(|error| {
+                Error::new(ErrorKind::Decode, error).with_request(
+                    bytes.method.clone(),
+                    Some(bytes.url.clone()),
+                    &bytes.sensitive_headers,
+                )
+            })?;
+            Ok(bytes.map(|_| value))
+        })
+    }
+
+    /// Reads the body as text, decoded with the `charset` of the
+    /// `Content-Type` header, the encoding of a BOM, or the client's
+    /// default charset. Malformed sequences are replaced.
+    ///
+    /// # Errors
+    /// Will return Err if the body couldn't be read or exceeds the maximum
+    /// body size.
+    pub fn text(self) -> impl Future<Item = Decoded<String>, Error = Error> {
+        let charset = content_type_charset(self.headers());
+        let default_charset = self.default_charset;
+        self.bytes().map(move |bytes| {
+            bytes.map(|bytes| match charset {
+                Some(charset) => charset.decode_with_bom_removal(&bytes).0.into_owned(),
+                None => default_charset.decode(&bytes).0.into_owned(),
+            })
+        })
+    }
+
+    /// Reads the body as text, decoded with `charset`, a label like
+    /// `"iso-8859-1"` or `"shift_jis"`, whatever the headers say.
+    /// Malformed sequences are replaced.
+    ///
+    /// # Errors
+    /// Will return Err if `charset` isn't a known charset, in which case
+    /// `Error::is_decode` is true, or if the body couldn't be read or
+    /// exceeds the maximum body size.
+    pub fn text_with_charset(
+        self,
+        charset: &str,
+    ) -> impl Future<Item = Decoded<String>, Error = Error> {
+        let charset = Encoding::for_label(charset.as_bytes()).ok_or_else(|| {
+            Error::new(ErrorKind::Decode, format!("unknown charset: {}", charset)).with_request(
+                self.method.clone(),
+                Some(self.url.clone()),
+                &self.sensitive_headers,
+            )
+        });
+        future::result(charset).and_then(move |charset| {
+            self.bytes().map(move |bytes| {
+                bytes.map(|bytes| charset.decode_with_bom_removal(&bytes).0.into_owned())
+            })
+        })
+    }
+
+    /// Reads the whole body.
+    ///
+    /// # Errors
+    /// Will return Err if the body couldn't be read or exceeds the maximum
+    /// body size, in which case `Error::is_body_too_large` is true.
+    pub fn bytes(self) -> impl Future<Item = Decoded<Vec<u8>>, Error = Error> {
+        let Response {
+            inner,
+            url,
+            method,
+            max_body_size,
+            sensitive_headers,
+            ..
+        } = self;
+        let (parts, body) = inner.into_parts();
+
+        let content_length = parts
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        let read = match (max_body_size, content_length) {
+            (Some(limit), Some(length)) if length > limit => {
+                Either::A(future::err(Error::body_too_large(limit)))
+            }
+            _ => Either::B(body.map_err(Error::from_body).fold(
+                Vec::new(),
+                move |mut bytes, chunk| {
+                    match max_body_size {
+                        Some(limit) if (bytes.len() + chunk.len()) as u64 > limit => {
+                            return Err(Error::body_too_large(limit));
+                        }
+                        _ => bytes.extend_from_slice(&chunk),
+                    }
+                    Ok(bytes)
+                },
+            )),
+        };
+
+        read.then(move |body| match body {
+            Ok(body) => Ok(Decoded {
+                value: body,
+                status: parts.status,
+                headers: parts.headers,
+                version: parts.version,
+                url,
+                method,
+                sensitive_headers,
+            }),
+            Err(error) => Err(error.with_request(method, Some(url), &sensitive_headers)),
+        })
+    }
+
+    pub(crate) fn method(&self) -> Option<&Method> {
+        self.method.as_ref()
+    }
+
+    pub(crate) fn sensitive_headers(&self) -> &Arc<SensitiveHeaders> {
+        &self.sensitive_headers
+    }
+
+    /// Replaces the body of the response with `f(body)`.
+    pub(crate) fn map_body<F>(self, f: F) -> Response
+    where
+        F: FnOnce(hyper::Body) -> hyper::Body,
+    {
+        Response {
+            inner: self.inner.map(f),
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("url", &redact_uri(&self.url))
+            .field("status", &self.status())
+            .field("version", &self.version())
+            .field("headers", &self.sensitive_headers.redact(self.headers()))
+            .field("default_charset", &self.default_charset.name())
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+/// Returns the encoding named by the `charset` parameter of the
+/// `Content-Type` header, if it is a known one.
+fn content_type_charset(headers: &HeaderMap) -> Option<&'static Encoding> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|parameter| {
+            let mut parameter = parameter.splitn(2, '=');
+            let name = parameter.next()?.trim();
+            let value = parameter.next()?.trim();
+            if name.eq_ignore_ascii_case("charset") {
+                Some(value.trim_matches('"'))
+            } else {
+                None
+            }
+        })
+        .next()
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+}
+
+/// A decoded response body, with the metadata of the response.
+pub struct Decoded<T> {
+    /// The decoded body.
+    pub value: T,
+    /// The status of the response.
+    pub status: StatusCode,
+    /// The headers of the response.
+    pub headers: HeaderMap,
+    /// The HTTP version of the response.
+    pub version: Version,
+    /// The url of the response.
+    pub url: Uri,
+    method: Option<Method>,
+    sensitive_headers: Arc<SensitiveHeaders>,
+}
+
+impl<T> Decoded<T> {
+    /// Returns the decoded body, dropping the metadata.
+    pub fn into_value(self) -> T {
+        self.value
+    }
+
+    pub(crate) fn method(&self) -> Option<&Method> {
+        self.method.as_ref()
+    }
+
+    pub(crate) fn sensitive_headers(&self) -> &Arc<SensitiveHeaders> {
+        &self.sensitive_headers
+    }
+
+    /// Replaces the decoded body with `f(value)`, keeping the metadata.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Decoded<U> {
+        Decoded {
+            value: f(self.value),
+            status: self.status,
+            headers: self.headers,
+            version: self.version,
+            url: self.url,
+            method: self.method,
+            sensitive_headers: self.sensitive_headers,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Decoded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Decoded")
+            .field("value", &self.value)
+            .field("status", &self.status)
+            .field("headers", &self.sensitive_headers.redact(&self.headers))
+            .field("version", &self.version)
+            .field("url", &redact_uri(&self.url))
+            .finish()
+    }
+}
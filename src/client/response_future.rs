@@ -1,16 +1,16 @@
-use failure::Error;
-use futures::{future, Async, Future, Poll, Stream};
+use client::error::{Error, BODY_SNIPPET_LENGTH};
+use client::response::{Decoded, Response};
+use futures::future::{self, Either, Loop};
+use futures::{Async, Future, Poll, Stream};
 use hyper;
 use serde::de::DeserializeOwned;
 use serde_json;
 use std::fmt;
 
-pub struct ResponseFuture(
-    pub Box<Future<Item = hyper::Response<hyper::Body>, Error = Error> + Send>,
-);
+pub struct ResponseFuture(pub Box<dyn Future<Item = Response, Error = Error> + Send>);
 
 impl Future for ResponseFuture {
-    type Item = hyper::Response<hyper::Body>;
+    type Item = Response;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -27,8 +27,13 @@ impl ResponseFuture {
     /// Deserialize the response json body into a `T`.
     /// Returns a Future containing the deserialized body.
     ///
+    /// The body is deserialized whatever the status of the response,
+    /// see `json_for_status` and `json_result` to handle error statuses.
+    ///
     /// # Errors
-    /// Will return Err if the body couldn't be deserialized into a `T`.
+    /// Will return Err if the body couldn't be read or exceeds the maximum
+    /// body size, or if it couldn't be deserialized into a `T`, in which
+    /// case `Error::is_decode` is true.
     ///
     /// # Examples
     ///
@@ -60,15 +65,249 @@ impl ResponseFuture {
     pub fn json<T>(self) -> impl Future<Item = T, Error = Error> + Sized
     where
         T: DeserializeOwned + fmt::Debug,
+    {
+        self.0.and_then(Response::json).map(Decoded::into_value)
+    }
+
+    /// Fails with a status error if the response has a client or server
+    /// error status, `4xx` or `5xx`. The error keeps the status, the
+    /// headers and the start of the body of the response.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate httper;
+    ///
+    /// use futures::Future;
+    /// use httper::client::HttperClient;
+    ///
+    /// fn main() {
+    ///     let httper_client = HttperClient::new();
+    ///
+    ///     httper_client
+    ///         .get("https://testing.local")
+    ///         .send()
+    ///         .error_for_status()
+    ///         .map_err(|error| {
+    ///             if let Some(status) = error.status() {
+    ///                 println!("{}: {:?}", status, error.body_snippet());
+    ///             }
+    ///         });
+    /// }
+    /// ```
+    pub fn error_for_status(self) -> ResponseFuture {
+        ResponseFuture(Box::new(self.0.and_then(|response| {
+            let status = response.status();
+            if !status.is_client_error() && !status.is_server_error() {
+                return Either::A(future::ok(response));
+            }
+
+            let method = response.method().cloned();
+            let url = response.url().clone();
+            let sensitive_headers = response.sensitive_headers().clone();
+            let (parts, body) = response.into_inner().into_parts();
+            Either::B(read_snippet(body).and_then(move |body| {
+                Err(
+                    Error::from_status(status, parts.headers, &body).with_request(
+                        method,
+                        Some(url),
+                        &sensitive_headers,
+                    ),
+                )
+            }))
+        })))
+    }
+
+    /// Deserialize the response json body into a `T`, like `json`, unless
+    /// the response has a client or server error status.
+    ///
+    /// # Errors
+    /// Will return a status error if the response has a `4xx` or `5xx`
+    /// status, see `error_for_status`, and the errors of `json` otherwise.
+    pub fn json_for_status<T>(self) -> impl Future<Item = T, Error = Error> + Sized
+    where
+        T: DeserializeOwned + fmt::Debug,
+    {
+        self.error_for_status().json()
+    }
+
+    /// Deserialize the response json body into an `E` if the response
+    /// has a client or server error status, `4xx` or `5xx`, and into a `T`
+    /// otherwise.
+    ///
+    /// # Errors
+    /// Will return Err if the body couldn't be read, exceeds the maximum
+    /// body size or couldn't be deserialized into a `T` for the other
+    /// responses. Error responses fail with a status error when their
+    /// body couldn't be deserialized into an `E`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate httper;
+    ///
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    ///
+    /// use futures::Future;
+    /// use httper::client::HttperClient;
+    ///
+    /// fn main() {
+    ///     #[derive(Debug, Deserialize)]
+    ///     struct Data {
+    ///         name: String,
+    ///     }
+    ///
+    ///     #[derive(Debug, Deserialize)]
+    ///     struct ApiError {
+    ///         message: String,
+    ///     }
+    ///
+    ///     let httper_client = HttperClient::new();
+    ///
+    ///     httper_client
+    ///         .get("https://testing.local")
+    ///         .send()
+    ///         .json_result::<Data, ApiError>()
+    ///         .map(|result| match result {
+    ///             Ok(data) => println!("Name: {}", data.name),
+    ///             Err(error) => println!("Rejected: {}", error.message),
+    ///         });
+    /// }
+    /// ```
+    pub fn json_result<T, E>(self) -> impl Future<Item = Result<T, E>, Error = Error> + Sized
+    where
+        T: DeserializeOwned + fmt::Debug,
+        E: DeserializeOwned + fmt::Debug,
     {
         self.0.and_then(|response| {
-            response
-                .into_body()
-                .map_err(Error::from)
-                .concat2()
-                .and_then(|body| {
-                    future::result(serde_json::from_slice::<T>(&body).map_err(Error::from))
-                })
+            let status = response.status();
+            if !status.is_client_error() && !status.is_server_error() {
+                return Either::A(response.json().map(|decoded| Ok(decoded.into_value())));
+            }
+
+            Either::B(response.bytes().and_then(|bytes| {
+                match serde_json::from_slice::<E>(&bytes.value) {
+                    Ok(error) => Ok(Err(error)),
+                    Err(_) => {
+                        let method = bytes.method().cloned();
+                        let sensitive_headers = bytes.sensitive_headers().clone();
+                        Err(
+                            Error::from_status(bytes.status, bytes.headers, &bytes.value)
+                                .with_request(method, Some(bytes.url), &sensitive_headers),
+                        )
+                    }
+                }
+            }))
         })
     }
+
+    /// Sets the maximum size in bytes of the body read by `json`, `text`
+    /// and `bytes`, overriding `HttperClientBuilder::max_body_size` for
+    /// this response. Pass `None` to read a body of any size.
+    ///
+    /// Bodies announcing a larger `Content-Length` fail right away, and
+    /// the others as soon as more bytes are received, with an error for
+    /// which `Error::is_body_too_large` is true.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate httper;
+    ///
+    /// use futures::Future;
+    /// use httper::client::HttperClient;
+    ///
+    /// fn main() {
+    ///     let httper_client = HttperClient::new();
+    ///
+    ///     httper_client
+    ///         .get("https://testing.local")
+    ///         .send()
+    ///         .max_body_size(64 * 1024)
+    ///         .text()
+    ///         .map_err(|error| {
+    ///             if error.is_body_too_large() {
+    ///                 println!("The response is too large");
+    ///             }
+    ///         });
+    /// }
+    /// ```
+    pub fn max_body_size<L: Into<Option<u64>>>(self, limit: L) -> ResponseFuture {
+        let limit = limit.into();
+        ResponseFuture(Box::new(
+            self.0.map(move |response| response.max_body_size(limit)),
+        ))
+    }
+
+    /// Reads the response body as text, decoded with the `charset` of
+    /// the `Content-Type` header, the encoding of a byte order mark, or
+    /// the client's default charset, see `Response::text`.
+    ///
+    /// # Errors
+    /// Will return Err if the body couldn't be read or exceeds the maximum
+    /// body size.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate httper;
+    ///
+    /// use futures::Future;
+    /// use httper::client::HttperClient;
+    ///
+    /// fn main() {
+    ///     let httper_client = HttperClient::builder()
+    ///         .default_charset("windows-1252")
+    ///         .build()
+    ///         .unwrap();
+    ///
+    ///     httper_client
+    ///         .get("https://testing.local")
+    ///         .send()
+    ///         .text()
+    ///         .map(|text| println!("{}", text));
+    /// }
+    /// ```
+    pub fn text(self) -> impl Future<Item = String, Error = Error> + Sized {
+        self.0.and_then(Response::text).map(Decoded::into_value)
+    }
+
+    /// Reads the response body as text, decoded with `charset` whatever
+    /// the headers say, see `Response::text_with_charset`.
+    ///
+    /// # Errors
+    /// Will return Err if `charset` isn't a known charset, or if the body
+    /// couldn't be read or exceeds the maximum body size.
+    pub fn text_with_charset(
+        self,
+        charset: &str,
+    ) -> impl Future<Item = String, Error = Error> + Sized {
+        let charset = charset.to_string();
+        self.0
+            .and_then(move |response| response.text_with_charset(&charset))
+            .map(Decoded::into_value)
+    }
+}
+
+/// Reads the start of `body` for a status error. A body that fails
+/// to be read is cut short.
+fn read_snippet(body: hyper::Body) -> impl Future<Item = Vec<u8>, Error = Error> {
+    future::loop_fn((body, Vec::new()), |(body, mut bytes)| {
+        body.into_future().then(move |result| match result {
+            Ok((Some(chunk), body)) => {
+                bytes.extend_from_slice(&chunk);
+                if bytes.len() < BODY_SNIPPET_LENGTH {
+                    Ok(Loop::Continue((body, bytes)))
+                } else {
+                    Ok(Loop::Break(bytes))
+                }
+            }
+            Ok((None, _)) | Err(_) => Ok(Loop::Break(bytes)),
+        })
+    })
 }
@@ -0,0 +1,323 @@
+//! Retry policies
+//!
+//! A `RetryPolicy` set with `HttperClientBuilder::retry`, or per request
+//! with `retry` on `Get` and `PayloadBuilder`, sends requests again when
+//! connecting failed or when the response status is one of the retryable
+//! statuses, `429`, `502`, `503` and `504` by default.
+//!
+//! Requests are only sent again on retryable statuses when their method
+//! is idempotent, unless `retry_non_idempotent` is set. Failed connects are
+//! retried for every method since the request was never sent.
+//!
+//! Attempts are separated by an exponentially growing backoff with full
+//! jitter, or by the delay given in the `Retry-After` header of `429` and
+//! `503` responses when it is longer. Responses asking for a delay longer
+//! than the maximum backoff are returned without retrying them. Request
+//! bodies are buffered so they can be sent again.
+
+use client::redirect::{SendFuture, SendRequest};
+use client::timeout::{from_hyper, from_timeout, TimeoutError};
+use failure::Error;
+use futures::future::{self, Loop};
+use futures::{Future, Stream};
+use http;
+use hyper::{self, header, Method, StatusCode};
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::timer::{Delay, Timeout};
+
+/// Decides how failed requests are retried.
+///
+/// # Examples
+///
+/// ```
+/// use httper::client::retry::RetryPolicy;
+/// use httper::client::HttperClient;
+/// use std::time::Duration;
+///
+/// let httper_client = HttperClient::builder()
+///     .retry(
+///         RetryPolicy::new()
+///             .max_attempts(5)
+///             .backoff(Duration::from_millis(50), Duration::from_secs(2))
+///             .deadline(Duration::from_secs(10)),
+///     )
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+    statuses: Vec<StatusCode>,
+    retry_non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// Creates a policy making up to 3 attempts, backing off from
+    /// 100 milliseconds up to 10 seconds between them.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            deadline: None,
+            statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_non_idempotent: false,
+        }
+    }
+
+    /// Creates a policy never retrying requests.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new().max_attempts(1)
+    }
+
+    /// Sets the maximum number of attempts, including the first one.
+    pub fn max_attempts(self, max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            ..self
+        }
+    }
+
+    /// Sets the backoff before the first retry and the maximum backoff.
+    /// The backoff doubles after each attempt.
+    ///
+    /// The maximum backoff also bounds the delays asked for with
+    /// `Retry-After`, responses asking for longer ones aren't retried.
+    pub fn backoff(self, initial_backoff: Duration, max_backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    /// Sets the time allowed for all attempts together, from sending the
+    /// first one. Exceeding it fails the request with `TimeoutError::Request`,
+    /// and no retry is scheduled past it.
+    pub fn deadline(self, deadline: Duration) -> RetryPolicy {
+        RetryPolicy {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Sets the response statuses that are retried.
+    pub fn statuses(self, statuses: Vec<StatusCode>) -> RetryPolicy {
+        RetryPolicy { statuses, ..self }
+    }
+
+    /// Controls whether requests with non-idempotent methods, like `POST`
+    /// and `PATCH`, are retried on retryable statuses. Defaults to `false`.
+    pub fn retry_non_idempotent(self, retry_non_idempotent: bool) -> RetryPolicy {
+        RetryPolicy {
+            retry_non_idempotent,
+            ..self
+        }
+    }
+
+    /// Returns the backoff before the retry following attempt `attempt`,
+    /// starting at 1, with full jitter applied.
+    fn backoff_after(&self, attempt: u32) -> Duration {
+        let exponent = cmp::min(attempt - 1, 31);
+        let backoff = cmp::min(
+            self.initial_backoff
+                .checked_mul(1 << exponent)
+                .unwrap_or(self.max_backoff),
+            self.max_backoff,
+        );
+
+        let millis = backoff.as_secs() * 1000 + u64::from(backoff.subsec_millis());
+        let random = RandomState::new().build_hasher().finish();
+        Duration::from_millis(random % (millis + 1))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new()
+    }
+}
+
+struct State {
+    parts: http::request::Parts,
+    body: Vec<u8>,
+    attempt: u32,
+}
+
+/// Sends `request` with `send`, retrying as allowed by `policy`.
+pub(crate) fn send(
+    request: hyper::Request<hyper::Body>,
+    policy: RetryPolicy,
+    send: SendRequest,
+) -> SendFuture {
+    if policy.max_attempts <= 1 {
+        return send(request);
+    }
+
+    let deadline = policy.deadline.map(|deadline| Instant::now() + deadline);
+    let (parts, body) = request.into_parts();
+
+    let attempts = body.concat2().map_err(from_hyper).and_then(move |body| {
+        let state = State {
+            parts,
+            body: body.to_vec(),
+            attempt: 1,
+        };
+
+        future::loop_fn(state, move |mut state| {
+            let mut request = hyper::Request::new(hyper::Body::from(state.body.clone()));
+            *request.method_mut() = state.parts.method.clone();
+            *request.uri_mut() = state.parts.uri.clone();
+            *request.version_mut() = state.parts.version;
+            *request.headers_mut() = state.parts.headers.clone();
+
+            let policy = policy.clone();
+            send(request).then(
+                move |result| -> Box<dyn Future<Item = _, Error = Error> + Send> {
+                    let delay = match retry_delay(&policy, &state, &result) {
+                        Some(delay) => delay,
+                        None => return Box::new(future::result(result.map(Loop::Break))),
+                    };
+
+                    let retry_at = Instant::now() + delay;
+                    if deadline.map_or(false, |deadline| retry_at >= deadline) {
+                        return Box::new(future::result(result.map(Loop::Break)));
+                    }
+
+                    state.attempt += 1;
+                    Box::new(
+                        Delay::new(retry_at)
+                            .map_err(Error::from)
+                            .map(|()| Loop::Continue(state)),
+                    )
+                },
+            )
+        })
+    });
+
+    match deadline {
+        Some(deadline) => Box::new(
+            Timeout::new_at(attempts, deadline)
+                .map_err(|error| from_timeout(error, TimeoutError::Request)),
+        ),
+        None => Box::new(attempts),
+    }
+}
+
+/// Returns the delay before retrying the attempt that ended with
+/// `result`, or `None` if it shouldn't be retried.
+fn retry_delay(
+    policy: &RetryPolicy,
+    state: &State,
+    result: &Result<hyper::Response<hyper::Body>, Error>,
+) -> Option<Duration> {
+    if state.attempt >= policy.max_attempts {
+        return None;
+    }
+
+    let backoff = policy.backoff_after(state.attempt);
+
+    match *result {
+        Ok(ref response) => {
+            let idempotent = is_idempotent(&state.parts.method);
+            if !policy.statuses.contains(&response.status())
+                || !(idempotent || policy.retry_non_idempotent)
+            {
+                return None;
+            }
+
+            match response.status() {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                    match retry_after(response) {
+                        Some(retry_after) if retry_after > policy.max_backoff => None,
+                        retry_after => Some(cmp::max(backoff, retry_after.unwrap_or_default())),
+                    }
+                }
+                _ => Some(backoff),
+            }
+        }
+        Err(ref error) if is_connect_error(error) => Some(backoff),
+        Err(_) => None,
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+fn is_connect_error(error: &Error) -> bool {
+    match error.downcast_ref::<hyper::Error>() {
+        Some(error) => error.is_connect(),
+        None => error.downcast_ref::<TimeoutError>() == Some(&TimeoutError::Connect),
+    }
+}
+
+/// Reads the delay in the `Retry-After` header of `response`,
+/// given either in seconds or as a date.
+fn retry_after(response: &hyper::Response<hyper::Body>) -> Option<Duration> {
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = retry_after.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    parse_http_date(retry_after)?
+        .duration_since(SystemTime::now())
+        .ok()
+}
+
+/// Parses a date in the IMF-fixdate format, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(crate) fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let mut fields = date.split_whitespace().skip(1);
+    let day = fields.next()?.parse::<u64>().ok()?;
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|month| Some(*month) == fields.clone().next())? as u64
+        + 1;
+    let year = fields.nth(1)?.parse::<u64>().ok()?;
+    let time = fields
+        .next()?
+        .split(':')
+        .map(|field| field.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if fields.next()? != "GMT" || time.len() != 3 || day == 0 || day > 31 || year < 1970 {
+        return None;
+    }
+
+    // Days since the epoch of the civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + time[0] * 3600 + time[1] * 60 + time[2];
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
@@ -0,0 +1,654 @@
+//! AWS Signature Version 4
+//!
+//! A `SigV4` signer set with `HttperClientBuilder::aws_sigv4` signs the
+//! requests of the client for S3, MinIO and the other services expecting
+//! AWS Signature Version 4, including retries and followed redirects.
+//! Requests setting their own `Authorization` header, or presigned with an
+//! `X-Amz-Signature` query parameter, are sent as is.
+//!
+//! How the payload is signed is set with `PayloadSigning`:
+//!
+//! - `Signed`, the default, signs the SHA-256 hash of the body,
+//!   which is buffered to compute it.
+//! - `Unsigned` signs the `UNSIGNED-PAYLOAD` placeholder instead,
+//!   and sends the body as it is read.
+//! - `Streaming` signs the body in chunks of 64 KiB while it is sent,
+//!   with the `aws-chunked` encoding of S3.
+//!
+//! Bodies of unknown length can't be hashed or chunked
+//! ahead, so they are always sent unsigned.
+//!
+//! S3 paths are encoded once in the canonical request and the paths of
+//! other services twice, as AWS expects. Paths aren't normalized.
+//!
+//! # Examples
+//!
+//! ```
+//! use httper::client::sigv4::{PayloadSigning, SigV4};
+//! use httper::client::HttperClient;
+//! use std::time::Duration;
+//!
+//! let signer = SigV4::new("access key id", "secret access key", "us-east-1", "s3")
+//!     .payload_signing(PayloadSigning::Streaming);
+//!
+//! let url = signer
+//!     .presign(&"GET".parse().unwrap(), "http://localhost:9000/bucket/object", Duration::from_secs(3600))
+//!     .unwrap();
+//!
+//! let httper_client = HttperClient::builder().aws_sigv4(signer).build().unwrap();
+//!
+//! httper_client
+//!     .put("http://localhost:9000/bucket/object")
+//!     .payload("payload")
+//!     .send();
+//! ```
+
+use client::error::Error as RequestError;
+use client::redirect::{SendFuture, SendRequest};
+use client::timeout::from_hyper;
+use failure::Error;
+use futures::future;
+use futures::{Async, Future, Poll, Stream};
+use hmac::{Hmac, Mac};
+use http;
+use hyper::body::Payload;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{self, Chunk, Method, Uri};
+use sha2::{Digest, Sha256};
+use std::error::Error as StdError;
+use std::fmt;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const STREAMING_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+
+/// The size of the chunks of streaming payloads.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The longest validity of presigned urls, 7 days in seconds.
+const MAX_PRESIGN_EXPIRES: u64 = 7 * 24 * 60 * 60;
+
+/// Headers that proxies or the client itself may change, so they aren't signed.
+const UNSIGNED_HEADERS: &[&str] = &[
+    "authorization",
+    "connection",
+    "expect",
+    "proxy-authorization",
+    "transfer-encoding",
+    "user-agent",
+];
+
+/// Signs requests with AWS Signature Version 4.
+#[derive(Clone, PartialEq)]
+pub struct SigV4 {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+    service: String,
+    payload_signing: PayloadSigning,
+}
+
+/// How the payload of requests is signed, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadSigning {
+    Signed,
+    Unsigned,
+    Streaming,
+}
+
+impl SigV4 {
+    /// Creates a signer with the given credentials, for the
+    /// `service`, like `s3`, in `region`, like `us-east-1`.
+    pub fn new<A, S, R, V>(access_key_id: A, secret_access_key: S, region: R, service: V) -> SigV4
+    where
+        A: Into<String>,
+        S: Into<String>,
+        R: Into<String>,
+        V: Into<String>,
+    {
+        SigV4 {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+            region: region.into(),
+            service: service.into(),
+            payload_signing: PayloadSigning::Signed,
+        }
+    }
+
+    /// Sets the session token of temporary credentials,
+    /// sent with `X-Amz-Security-Token`.
+    pub fn session_token<T: Into<String>>(self, session_token: T) -> SigV4 {
+        SigV4 {
+            session_token: Some(session_token.into()),
+            ..self
+        }
+    }
+
+    /// Sets how the payload of requests is signed.
+    pub fn payload_signing(self, payload_signing: PayloadSigning) -> SigV4 {
+        SigV4 {
+            payload_signing,
+            ..self
+        }
+    }
+
+    /// Returns `url` presigned for a `method` request, valid for `expires_in`,
+    /// at most 7 days. The url can be requested without credentials.
+    ///
+    /// # Errors
+    /// Will return Err if `url` couldn't be parsed into a `hyper::Uri`
+    /// or has no host, or if `expires_in` is zero or longer than 7 days.
+    pub fn presign(
+        &self,
+        method: &Method,
+        url: &str,
+        expires_in: Duration,
+    ) -> Result<String, RequestError> {
+        self.presign_at(method, url, expires_in, SystemTime::now())
+    }
+
+    /// Presigns `url` as if at `time`, see `presign`.
+    pub(crate) fn presign_at(
+        &self,
+        method: &Method,
+        url: &str,
+        expires_in: Duration,
+        time: SystemTime,
+    ) -> Result<String, RequestError> {
+        if expires_in.as_secs() == 0 || expires_in.as_secs() > MAX_PRESIGN_EXPIRES {
+            return Err(RequestError::builder(format!(
+                "presigned urls must expire within 1 to {} seconds, not {:?}",
+                MAX_PRESIGN_EXPIRES, expires_in
+            )));
+        }
+
+        let uri = url.parse::<Uri>().map_err(RequestError::builder)?;
+        let host =
+            host(&uri).ok_or_else(|| RequestError::builder(format!("no host in {}", url)))?;
+        let date_time = format_date_time(time);
+
+        let mut params = vec![
+            ("X-Amz-Algorithm", ALGORITHM.to_string()),
+            (
+                "X-Amz-Credential",
+                format!("{}/{}", self.access_key_id, self.scope(&date_time)),
+            ),
+            ("X-Amz-Date", date_time.clone()),
+            ("X-Amz-Expires", expires_in.as_secs().to_string()),
+        ];
+        if let Some(ref session_token) = self.session_token {
+            params.push(("X-Amz-Security-Token", session_token.clone()));
+        }
+        params.push(("X-Amz-SignedHeaders", "host".to_string()));
+
+        let params = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, uri_encode(value.as_bytes(), true)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let query = match uri.query() {
+            Some(query) if !query.is_empty() => format!("{}&{}", query, params),
+            _ => params,
+        };
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\n\nhost\n{}",
+            method,
+            self.canonical_uri(uri.path()),
+            canonical_query(&query),
+            host,
+            UNSIGNED_PAYLOAD
+        );
+        let signature = self.signature(&date_time, &canonical_request);
+
+        Ok(format!(
+            "{}://{}{}?{}&X-Amz-Signature={}",
+            uri.scheme_part().map_or("https", |scheme| scheme.as_str()),
+            host,
+            uri.path(),
+            query,
+            signature
+        ))
+    }
+
+    /// Signs the request described by `parts` with `payload_hash` as if at
+    /// `time`. Adds the `Host`, `X-Amz-Date`, `X-Amz-Security-Token` and
+    /// `Authorization` headers and returns the signature.
+    pub(crate) fn sign(
+        &self,
+        parts: &mut http::request::Parts,
+        payload_hash: &str,
+        time: SystemTime,
+    ) -> Result<String, Error> {
+        let date_time = format_date_time(time);
+        if !parts.headers.contains_key(header::HOST) {
+            if let Some(host) = host(&parts.uri) {
+                parts
+                    .headers
+                    .insert(header::HOST, HeaderValue::from_str(&host)?);
+            }
+        }
+        parts
+            .headers
+            .insert("x-amz-date", HeaderValue::from_str(&date_time)?);
+        if let Some(ref session_token) = self.session_token {
+            parts.headers.insert(
+                "x-amz-security-token",
+                HeaderValue::from_str(session_token)?,
+            );
+        }
+
+        let (canonical_headers, signed_headers) = canonical_headers(&parts.headers);
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            parts.method,
+            self.canonical_uri(parts.uri.path()),
+            canonical_query(parts.uri.query().unwrap_or("")),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+        let signature = self.signature(&date_time, &canonical_request);
+
+        let mut authorization = HeaderValue::from_str(&format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM,
+            self.access_key_id,
+            self.scope(&date_time),
+            signed_headers,
+            signature
+        ))?;
+        authorization.set_sensitive(true);
+        parts.headers.insert(header::AUTHORIZATION, authorization);
+        Ok(signature)
+    }
+
+    /// Returns the signer of the chunks following the `seed_signature`
+    /// of a streaming request signed at `time`.
+    pub(crate) fn chunk_signer(&self, seed_signature: String, time: SystemTime) -> ChunkSigner {
+        let date_time = format_date_time(time);
+        ChunkSigner {
+            key: self.signing_key(&date_time),
+            scope: self.scope(&date_time),
+            date_time,
+            previous_signature: seed_signature,
+        }
+    }
+
+    fn scope(&self, date_time: &str) -> String {
+        format!(
+            "{}/{}/{}/aws4_request",
+            &date_time[..8],
+            self.region,
+            self.service
+        )
+    }
+
+    fn signing_key(&self, date_time: &str) -> Vec<u8> {
+        let key = format!("AWS4{}", self.secret_access_key);
+        [&date_time[..8], &self.region, &self.service, "aws4_request"]
+            .iter()
+            .fold(key.into_bytes(), |key, data| hmac(&key, data.as_bytes()))
+    }
+
+    fn signature(&self, date_time: &str, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            date_time,
+            self.scope(date_time),
+            hash(canonical_request.as_bytes())
+        );
+        hex(&hmac(
+            &self.signing_key(date_time),
+            string_to_sign.as_bytes(),
+        ))
+    }
+
+    /// Encodes each segment of `path`, once for S3 and twice, as
+    /// it is already encoded in the uri, for the other services.
+    fn canonical_uri(&self, path: &str) -> String {
+        if path.is_empty() {
+            return "/".to_string();
+        }
+        if self.service == "s3" {
+            uri_encode(&percent_decode(path), false)
+        } else {
+            uri_encode(path.as_bytes(), false)
+        }
+    }
+}
+
+impl fmt::Debug for SigV4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SigV4")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"...")
+            .field("session_token", &self.session_token.as_ref().map(|_| "..."))
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .field("payload_signing", &self.payload_signing)
+            .finish()
+    }
+}
+
+/// Signs the chunks of a streaming payload, each signature
+/// chaining the previous one.
+pub(crate) struct ChunkSigner {
+    key: Vec<u8>,
+    date_time: String,
+    scope: String,
+    previous_signature: String,
+}
+
+impl ChunkSigner {
+    /// Returns the signature of `chunk`, the next one of the payload.
+    pub(crate) fn sign(&mut self, chunk: &[u8]) -> String {
+        let string_to_sign = format!(
+            "{}-PAYLOAD\n{}\n{}\n{}\n{}\n{}",
+            ALGORITHM,
+            self.date_time,
+            self.scope,
+            self.previous_signature,
+            hash(b""),
+            hash(chunk)
+        );
+        self.previous_signature = hex(&hmac(&self.key, string_to_sign.as_bytes()));
+        self.previous_signature.clone()
+    }
+
+    /// Encodes the signed `chunk` in the `aws-chunked` encoding.
+    fn encode(&mut self, chunk: &[u8]) -> Chunk {
+        let signature = self.sign(chunk);
+        let mut encoded =
+            format!("{:x};chunk-signature={}\r\n", chunk.len(), signature).into_bytes();
+        encoded.extend_from_slice(chunk);
+        encoded.extend_from_slice(b"\r\n");
+        Chunk::from(encoded)
+    }
+}
+
+/// Returns the length of a payload of `length` bytes in the `aws-chunked`
+/// encoding, counting the final empty chunk.
+pub(crate) fn encoded_length(length: u64) -> u64 {
+    // `<hex length>;chunk-signature=<signature>\r\n<chunk>\r\n`
+    let chunk_length = |length: u64| format!("{:x}", length).len() as u64 + 85 + length;
+    let chunk_size = CHUNK_SIZE as u64;
+    let encoded = length / chunk_size * chunk_length(chunk_size) + chunk_length(0);
+    match length % chunk_size {
+        0 => encoded,
+        rest => encoded + chunk_length(rest),
+    }
+}
+
+/// Signs `request` with `signer` and sends it with `send`.
+pub(crate) fn send(
+    request: hyper::Request<hyper::Body>,
+    signer: &SigV4,
+    send: SendRequest,
+) -> SendFuture {
+    let presigned = request
+        .uri()
+        .query()
+        .map_or(false, |query| query.contains("X-Amz-Signature="));
+    if presigned || request.headers().contains_key(header::AUTHORIZATION) {
+        return send(request);
+    }
+
+    let signer = signer.clone();
+    let (mut parts, body) = request.into_parts();
+    let time = SystemTime::now();
+
+    match (signer.payload_signing, body.content_length()) {
+        (PayloadSigning::Signed, Some(_)) => Box::new(
+            body.concat2()
+                .map_err(from_hyper)
+                .and_then(move |body| {
+                    let payload_hash = hash(&body);
+                    parts.headers.insert(
+                        "x-amz-content-sha256",
+                        HeaderValue::from_str(&payload_hash)?,
+                    );
+                    signer.sign(&mut parts, &payload_hash, time)?;
+                    Ok(hyper::Request::from_parts(parts, hyper::Body::from(body)))
+                })
+                .and_then(move |request| send(request)),
+        ),
+        (PayloadSigning::Streaming, Some(length)) => {
+            let encoding = match parts.headers.get(header::CONTENT_ENCODING) {
+                Some(encoding) => format!("aws-chunked,{}", encoding.to_str().unwrap_or("")),
+                None => "aws-chunked".to_string(),
+            };
+            let signed = HeaderValue::from_str(&encoding)
+                .map_err(Error::from)
+                .and_then(|encoding| {
+                    let headers = &mut parts.headers;
+                    headers.insert(header::CONTENT_ENCODING, encoding);
+                    headers.insert(
+                        header::CONTENT_LENGTH,
+                        HeaderValue::from(encoded_length(length)),
+                    );
+                    headers.insert("x-amz-decoded-content-length", HeaderValue::from(length));
+                    headers.insert(
+                        "x-amz-content-sha256",
+                        HeaderValue::from_static(STREAMING_PAYLOAD),
+                    );
+                    signer.sign(&mut parts, STREAMING_PAYLOAD, time)
+                });
+
+            match signed {
+                Ok(seed_signature) => {
+                    let body = ChunkedBody {
+                        body,
+                        buffer: Vec::new(),
+                        signer: Some(signer.chunk_signer(seed_signature, time)),
+                    };
+                    send(hyper::Request::from_parts(
+                        parts,
+                        hyper::Body::wrap_stream(body),
+                    ))
+                }
+                Err(error) => Box::new(future::err(error)),
+            }
+        }
+        _ => {
+            parts.headers.insert(
+                "x-amz-content-sha256",
+                HeaderValue::from_static(UNSIGNED_PAYLOAD),
+            );
+            match signer.sign(&mut parts, UNSIGNED_PAYLOAD, time) {
+                Ok(_) => send(hyper::Request::from_parts(parts, body)),
+                Err(error) => Box::new(future::err(error)),
+            }
+        }
+    }
+}
+
+/// A request body signed in chunks while it is sent.
+struct ChunkedBody {
+    body: hyper::Body,
+    buffer: Vec<u8>,
+    signer: Option<ChunkSigner>,
+}
+
+impl Stream for ChunkedBody {
+    type Item = Chunk;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let signer = match self.signer {
+                Some(ref mut signer) => signer,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            if self.buffer.len() >= CHUNK_SIZE {
+                let rest = self.buffer.split_off(CHUNK_SIZE);
+                let chunk = mem::replace(&mut self.buffer, rest);
+                return Ok(Async::Ready(Some(signer.encode(&chunk))));
+            }
+
+            match self.body.poll()? {
+                Async::Ready(Some(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Async::Ready(None) if !self.buffer.is_empty() => {
+                    let chunk = mem::take(&mut self.buffer);
+                    return Ok(Async::Ready(Some(signer.encode(&chunk))));
+                }
+                Async::Ready(None) => {
+                    let chunk = signer.encode(&[]);
+                    self.signer = None;
+                    return Ok(Async::Ready(Some(chunk)));
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Returns the canonical headers and the signed header names of `headers`.
+fn canonical_headers(headers: &HeaderMap) -> (String, String) {
+    let mut names = headers
+        .keys()
+        .filter(|name| !UNSIGNED_HEADERS.contains(&name.as_str()))
+        .collect::<Vec<&HeaderName>>();
+    names.sort_by_key(|name| name.as_str());
+
+    let canonical = names
+        .iter()
+        .map(|name| {
+            let values = headers
+                .get_all(*name)
+                .iter()
+                .map(|value| {
+                    String::from_utf8_lossy(value.as_bytes())
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect::<Vec<_>>();
+            format!("{}:{}\n", name, values.join(","))
+        })
+        .collect();
+    let signed = names
+        .iter()
+        .map(|name| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    (canonical, signed)
+}
+
+/// Encodes the names and values of `query` and sorts them.
+fn canonical_query(query: &str) -> String {
+    let mut params = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let mut param = param.splitn(2, '=');
+            let name = uri_encode(&percent_decode(param.next().unwrap_or("")), true);
+            let value = uri_encode(&percent_decode(param.next().unwrap_or("")), true);
+            (name, value)
+        })
+        .collect::<Vec<_>>();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encodes all the bytes but the unreserved characters,
+/// and `/` unless `encode_slash` is set.
+fn uri_encode(value: &[u8], encode_slash: bool) -> String {
+    value
+        .iter()
+        .map(|&byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match (bytes[i], value.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Returns the `Host` header hyper sends for `uri`.
+fn host(uri: &Uri) -> Option<String> {
+    let host = uri.host()?;
+    Some(match uri.port_part() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+/// Formats `time` like `20150830T123600Z`.
+fn format_date_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    // The civil date of the days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = seconds / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = seconds % 86_400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.input(data);
+    mac.result().code().to_vec()
+}
+
+fn hash(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
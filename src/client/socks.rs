@@ -0,0 +1,163 @@
+//! The client side of the SOCKS5 protocol, see RFC 1928 and RFC 1929.
+
+use futures::{future, Future};
+use std::error::Error as StdError;
+use std::net::IpAddr;
+use tokio::io::{read_exact, write_all, AsyncRead, AsyncWrite};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+
+type SocksFuture<T> = Box<dyn Future<Item = T, Error = Box<dyn StdError + Send + Sync>> + Send>;
+
+/// The address a SOCKS5 proxy is asked to connect to.
+#[derive(Debug, Clone)]
+pub(crate) enum Address {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+/// Asks the SOCKS5 proxy at the other end of `stream` to connect
+/// to `address` and `port`, authenticating with `credentials`.
+pub(crate) fn connect<S>(
+    stream: S,
+    address: Address,
+    port: u16,
+    credentials: Option<(String, String)>,
+) -> SocksFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let greeting = match credentials {
+        Some(_) => vec![VERSION, 2, NO_AUTHENTICATION, USERNAME_PASSWORD],
+        None => vec![VERSION, 1, NO_AUTHENTICATION],
+    };
+
+    Box::new(
+        write_all(stream, greeting)
+            .and_then(|(stream, _)| read_exact(stream, [0; 2]))
+            .map_err(Into::into)
+            .and_then(move |(stream, reply)| -> SocksFuture<S> {
+                if reply[0] != VERSION {
+                    return Box::new(future::err("proxy isn't a SOCKS5 proxy".into()));
+                }
+
+                match (reply[1], credentials) {
+                    (NO_AUTHENTICATION, _) => Box::new(future::ok(stream)),
+                    (USERNAME_PASSWORD, Some((username, password))) => {
+                        authenticate(stream, &username, &password)
+                    }
+                    (NO_ACCEPTABLE_METHODS, _) => Box::new(future::err(
+                        "SOCKS5 proxy requires an unsupported authentication method".into(),
+                    )),
+                    (method, _) => Box::new(future::err(
+                        format!("SOCKS5 proxy selected an unknown method: {}", method).into(),
+                    )),
+                }
+            })
+            .and_then(move |stream| request(stream, &address, port)),
+    )
+}
+
+/// Performs the username/password authentication of RFC 1929.
+fn authenticate<S>(stream: S, username: &str, password: &str) -> SocksFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    // The lengths have been checked by `Proxy::basic_auth`.
+    let mut message = vec![1, username.len() as u8];
+    message.extend_from_slice(username.as_bytes());
+    message.push(password.len() as u8);
+    message.extend_from_slice(password.as_bytes());
+
+    Box::new(
+        write_all(stream, message)
+            .and_then(|(stream, _)| read_exact(stream, [0; 2]))
+            .map_err(Into::into)
+            .and_then(|(stream, reply)| {
+                if reply[1] == 0 {
+                    Ok(stream)
+                } else {
+                    Err("SOCKS5 proxy authentication failed".into())
+                }
+            }),
+    )
+}
+
+/// Sends the `CONNECT` request and reads the reply.
+fn request<S>(stream: S, address: &Address, port: u16) -> SocksFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let mut message = vec![VERSION, CONNECT, 0];
+    match *address {
+        Address::Ip(IpAddr::V4(ip)) => {
+            message.push(1);
+            message.extend_from_slice(&ip.octets());
+        }
+        Address::Ip(IpAddr::V6(ip)) => {
+            message.push(4);
+            message.extend_from_slice(&ip.octets());
+        }
+        Address::Domain(ref domain) => {
+            if domain.len() > 255 {
+                return Box::new(future::err(
+                    format!("host name too long for SOCKS5: {}", domain).into(),
+                ));
+            }
+            message.push(3);
+            message.push(domain.len() as u8);
+            message.extend_from_slice(domain.as_bytes());
+        }
+    }
+    message.extend_from_slice(&[(port >> 8) as u8, port as u8]);
+
+    Box::new(
+        write_all(stream, message)
+            .and_then(|(stream, _)| read_exact(stream, [0; 5]))
+            .map_err(Into::into)
+            .and_then(|(stream, reply)| -> SocksFuture<S> {
+                if reply[1] != 0 {
+                    return Box::new(future::err(reply_error(reply[1]).into()));
+                }
+
+                // The reply ends with the address bound by the proxy, of which
+                // the first byte has been read, followed by the port.
+                let remaining = match reply[3] {
+                    1 => 4 - 1 + 2,
+                    4 => 16 - 1 + 2,
+                    3 => reply[4] as usize + 2,
+                    kind => {
+                        return Box::new(future::err(
+                            format!("SOCKS5 proxy replied with unknown address type: {}", kind)
+                                .into(),
+                        ))
+                    }
+                };
+
+                Box::new(
+                    read_exact(stream, vec![0; remaining])
+                        .map(|(stream, _)| stream)
+                        .map_err(Into::into),
+                )
+            }),
+    )
+}
+
+fn reply_error(reply: u8) -> String {
+    let reason = match reply {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    };
+    format!("SOCKS5 proxy couldn't connect: {}", reason)
+}
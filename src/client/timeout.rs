@@ -0,0 +1,236 @@
+use failure::Error;
+use futures::{Async, Future, Poll, Stream};
+use hyper::{
+    self,
+    client::connect::{Connect, Connected, Destination},
+};
+use std::cell::Cell;
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::timer::{self, Delay};
+
+/// The timeouts that can be applied to a request.
+///
+/// A `None` value means that no timeout is applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timeouts {
+    /// The time allowed to establish a connection, including the TLS handshake.
+    pub connect: Option<Duration>,
+    /// The total time allowed for the request, from sending it until the
+    /// whole response body has been read.
+    pub request: Option<Duration>,
+    /// The time allowed to pass between two reads of the response body.
+    pub read: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Returns these timeouts with any unset value
+    /// taken from `defaults`.
+    pub fn or(self, defaults: Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            request: self.request.or(defaults.request),
+            read: self.read.or(defaults.read),
+        }
+    }
+}
+
+/// The error returned when a request timed out.
+///
+/// It is the `source` of the `Error` of requests that timed out,
+/// retrieve it with `error.source()` and `downcast_ref::<TimeoutError>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutError {
+    /// A connection couldn't be established in time.
+    Connect,
+    /// The request didn't finish before its deadline.
+    Request,
+    /// The response body stalled for longer than the read timeout.
+    Read,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeoutError::Connect => f.write_str("connect timed out"),
+            TimeoutError::Request => f.write_str("request timed out"),
+            TimeoutError::Read => f.write_str("body read timed out"),
+        }
+    }
+}
+
+impl StdError for TimeoutError {}
+
+/// Converts a `hyper::Error` into an `Error`, surfacing
+/// a `TimeoutError` if one caused it.
+pub(crate) fn from_hyper(error: hyper::Error) -> Error {
+    match find_timeout(&error) {
+        Some(timeout) => Error::from(timeout),
+        None => Error::from(error),
+    }
+}
+
+fn find_timeout(error: &(dyn StdError + 'static)) -> Option<TimeoutError> {
+    match error.downcast_ref::<TimeoutError>() {
+        Some(timeout) => Some(*timeout),
+        None => error.source().and_then(find_timeout),
+    }
+}
+
+/// Maps the error of a `tokio::timer::Timeout` into
+/// an `Error` using `elapsed` when the deadline was reached.
+pub(crate) fn from_timeout(error: timer::timeout::Error<Error>, elapsed: TimeoutError) -> Error {
+    if error.is_elapsed() {
+        Error::from(elapsed)
+    } else if error.is_inner() {
+        error.into_inner().expect("checked is_inner")
+    } else {
+        error
+            .into_timer()
+            .map(Error::from)
+            .expect("timeout error is either elapsed, inner or timer")
+    }
+}
+
+/// A connector that fails with `TimeoutError::Connect` if the
+/// wrapped connector doesn't connect within the given duration.
+#[derive(Debug, Clone)]
+pub struct TimeoutConnector<C> {
+    inner: C,
+    timeout: Option<Duration>,
+}
+
+impl<C> TimeoutConnector<C> {
+    /// Creates a new `TimeoutConnector` wrapping `inner`.
+    pub fn new(inner: C, timeout: Option<Duration>) -> Self {
+        TimeoutConnector { inner, timeout }
+    }
+}
+
+thread_local! {
+    // The connect timeout of the request being polled, if it overrides
+    // the connector's one, see `WithConnectTimeout`.
+    static CONNECT_TIMEOUT: Cell<Option<Option<Duration>>> = Cell::new(None);
+}
+
+/// A request future whose connections are made with `timeout` instead of
+/// the connect timeout of the client's `TimeoutConnector`.
+///
+/// hyper connects while the request future is polled, but only passes the
+/// `Destination` to the connector, so the timeout is set for the duration
+/// of each poll.
+pub(crate) struct WithConnectTimeout<F> {
+    inner: F,
+    timeout: Option<Duration>,
+}
+
+impl<F> WithConnectTimeout<F> {
+    pub(crate) fn new(inner: F, timeout: Option<Duration>) -> Self {
+        WithConnectTimeout { inner, timeout }
+    }
+}
+
+impl<F: Future> Future for WithConnectTimeout<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Restores the previous timeout, even if `poll` panics.
+        struct Reset(Option<Option<Duration>>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                CONNECT_TIMEOUT.with(|timeout| timeout.set(self.0));
+            }
+        }
+
+        let _reset = Reset(CONNECT_TIMEOUT.with(|timeout| timeout.replace(Some(self.timeout))));
+        self.inner.poll()
+    }
+}
+
+impl<C> Connect for TimeoutConnector<C>
+where
+    C: Connect,
+    C::Future: 'static,
+    C::Error: 'static,
+{
+    type Transport = C::Transport;
+    type Error = Box<dyn StdError + Send + Sync>;
+    type Future = Box<dyn Future<Item = (C::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let connecting = self.inner.connect(dst).map_err(Into::into);
+        let timeout = CONNECT_TIMEOUT.with(Cell::get).unwrap_or(self.timeout);
+
+        match timeout {
+            None => Box::new(connecting),
+            Some(timeout) => Box::new(timer::Timeout::new(connecting, timeout).map_err(|error| {
+                if error.is_elapsed() {
+                    Box::new(TimeoutError::Connect) as Self::Error
+                } else if error.is_inner() {
+                    error.into_inner().expect("checked is_inner")
+                } else {
+                    error
+                        .into_timer()
+                        .map(|error| Box::new(error) as Self::Error)
+                        .expect("timeout error is either elapsed, inner or timer")
+                }
+            })),
+        }
+    }
+}
+
+/// A response body that fails with `TimeoutError::Request` once `deadline`
+/// has passed and with `TimeoutError::Read` if no data arrives for `read`.
+pub(crate) struct TimeoutBody {
+    body: hyper::Body,
+    deadline: Option<Delay>,
+    read: Option<(Duration, Delay)>,
+}
+
+impl TimeoutBody {
+    pub(crate) fn new(
+        body: hyper::Body,
+        deadline: Option<Instant>,
+        read: Option<Duration>,
+    ) -> Self {
+        TimeoutBody {
+            body,
+            deadline: deadline.map(Delay::new),
+            read: read.map(|read| (read, Delay::new(Instant::now() + read))),
+        }
+    }
+}
+
+impl Stream for TimeoutBody {
+    type Item = hyper::Chunk;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.body.poll()? {
+            Async::Ready(chunk) => {
+                if let Some((read, ref mut delay)) = self.read {
+                    delay.reset(Instant::now() + read);
+                }
+                return Ok(Async::Ready(chunk));
+            }
+            Async::NotReady => {}
+        }
+
+        if let Some(ref mut deadline) = self.deadline {
+            if let Async::Ready(()) = deadline.poll()? {
+                return Err(Box::new(TimeoutError::Request));
+            }
+        }
+
+        if let Some((_, ref mut delay)) = self.read {
+            if let Async::Ready(()) = delay.poll()? {
+                return Err(Box::new(TimeoutError::Read));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
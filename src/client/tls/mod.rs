@@ -0,0 +1,209 @@
+//! TLS configuration
+//!
+//! The TLS backend is selected with cargo features: `native-tls`
+//! (the default) uses the platform's TLS library and `rustls-tls`
+//! uses [rustls](https://github.com/ctz/rustls). Both expose the same API.
+//!
+//! The rustls backend only connects to hosts by their DNS name: HTTPS
+//! requests to IP addresses, like `https://127.0.0.1`, fail to connect.
+
+use client::error::Error;
+use failure;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "native-tls")]
+#[path = "native.rs"]
+mod backend;
+
+#[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+#[path = "rustls.rs"]
+mod backend;
+
+pub(crate) use self::backend::TlsConnector;
+pub use self::backend::TlsStream;
+
+/// A certificate that can be added as a trusted root.
+#[derive(Clone)]
+pub struct Certificate {
+    der: Vec<u8>,
+}
+
+impl Certificate {
+    /// Parses a PEM encoded certificate.
+    ///
+    /// # Errors
+    /// Will return Err if `pem` doesn't contain a valid certificate.
+    pub fn from_pem(pem: &[u8]) -> Result<Certificate, Error> {
+        Certificate::from_pem_bundle(pem)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::builder("no certificate found in PEM"))
+    }
+
+    /// Parses a DER encoded certificate.
+    ///
+    /// # Errors
+    /// Will return Err if `der` isn't a valid certificate.
+    pub fn from_der(der: &[u8]) -> Result<Certificate, Error> {
+        backend::check_der_certificate(der).map_err(Error::builder_from_failure)?;
+        Ok(Certificate { der: der.to_vec() })
+    }
+
+    /// Parses every certificate in a PEM encoded bundle.
+    ///
+    /// # Errors
+    /// Will return Err if any of the certificates in `pem` is invalid.
+    pub fn from_pem_bundle(pem: &[u8]) -> Result<Vec<Certificate>, Error> {
+        backend::parse_pem_certificates(pem)
+            .map_err(Error::builder_from_failure)?
+            .into_iter()
+            .map(|der| Certificate::from_der(&der))
+            .collect()
+    }
+
+    /// Reads the certificates in the file at `path`, which may either be
+    /// a PEM bundle or a single DER encoded certificate.
+    ///
+    /// # Errors
+    /// Will return Err if the file couldn't be read or contains
+    /// an invalid certificate.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use httper::client::tls::Certificate;
+    /// use httper::client::HttperClient;
+    ///
+    /// let httper_client = Certificate::from_file("/etc/ssl/private-ca.pem")
+    ///     .unwrap()
+    ///     .into_iter()
+    ///     .fold(HttperClient::builder(), |builder, certificate| {
+    ///         builder.add_root_certificate(certificate)
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Certificate>, Error> {
+        let contents = fs::read(path).map_err(Error::builder)?;
+
+        if is_pem(&contents) {
+            Certificate::from_pem_bundle(&contents)
+        } else {
+            Ok(vec![Certificate::from_der(&contents)?])
+        }
+    }
+
+    /// Reads the certificates of every `.pem`, `.crt`, `.cer`
+    /// and `.der` file in the directory at `path`.
+    ///
+    /// # Errors
+    /// Will return Err if the directory or any of its certificate
+    /// files couldn't be read or contains an invalid certificate.
+    pub fn from_dir<P: AsRef<Path>>(path: P) -> Result<Vec<Certificate>, Error> {
+        let mut paths = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(Error::builder)?;
+        paths.sort();
+
+        let mut certificates = Vec::new();
+        for path in paths.into_iter().filter(|path| is_certificate_file(path)) {
+            certificates.extend(Certificate::from_file(path)?);
+        }
+
+        Ok(certificates)
+    }
+
+    /// Returns the DER encoding of this certificate.
+    pub fn to_der(&self) -> &[u8] {
+        &self.der
+    }
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Certificate").finish()
+    }
+}
+
+/// A client certificate and private key presented to
+/// servers requesting client authentication.
+#[derive(Clone)]
+pub struct Identity(backend::Identity);
+
+impl Identity {
+    /// Parses a DER encoded PKCS #12 archive protected by `password`.
+    ///
+    /// Only supported by the `native-tls` backend.
+    ///
+    /// # Errors
+    /// Will return Err if the archive is invalid, the password is wrong
+    /// or the TLS backend doesn't support PKCS #12.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Result<Identity, Error> {
+        backend::Identity::from_pkcs12(der, password)
+            .map(Identity)
+            .map_err(Error::builder_from_failure)
+    }
+
+    /// Parses a PEM encoded certificate chain and its PEM encoded PKCS #8 private key.
+    ///
+    /// # Errors
+    /// Will return Err if the certificates or the key are invalid.
+    pub fn from_pem(certificates: &[u8], key: &[u8]) -> Result<Identity, Error> {
+        backend::Identity::from_pem(certificates, key)
+            .map(Identity)
+            .map_err(Error::builder_from_failure)
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Identity").finish()
+    }
+}
+
+/// A TLS protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+/// The TLS settings of a `HttperClientBuilder`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsConfig {
+    pub(crate) root_certificates: Vec<Certificate>,
+    pub(crate) disable_built_in_roots: bool,
+    pub(crate) identity: Option<Identity>,
+    pub(crate) min_version: Option<TlsVersion>,
+    pub(crate) max_version: Option<TlsVersion>,
+    pub(crate) accept_invalid_certs: bool,
+    pub(crate) accept_invalid_hostnames: bool,
+    pub(crate) alpn_http2: bool,
+}
+
+impl TlsConfig {
+    /// Builds a `TlsConnector` for the enabled backend using these settings.
+    pub(crate) fn build_tls_connector(&self) -> Result<TlsConnector, failure::Error> {
+        TlsConnector::new(self)
+    }
+}
+
+fn is_certificate_file(path: &Path) -> bool {
+    let extension = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.to_lowercase(),
+        None => return false,
+    };
+
+    path.is_file() && ["pem", "crt", "cer", "der"].contains(&extension.as_str())
+}
+
+fn is_pem(contents: &[u8]) -> bool {
+    contents.windows(11).any(|window| window == b"-----BEGIN ")
+}
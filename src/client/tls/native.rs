@@ -0,0 +1,113 @@
+use super::{TlsConfig, TlsVersion};
+use failure::Error;
+use futures::Future;
+use native_tls;
+use std::error::Error as StdError;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tls;
+
+/// A TLS encrypted stream, provided by `native-tls`.
+pub type TlsStream<S> = tokio_tls::TlsStream<S>;
+
+pub(crate) fn check_der_certificate(der: &[u8]) -> Result<(), Error> {
+    native_tls::Certificate::from_der(der)?;
+    Ok(())
+}
+
+pub(crate) fn parse_pem_certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    native_tls::Certificate::stack_from_pem(pem)?
+        .iter()
+        .map(|certificate| certificate.to_der().map_err(Error::from))
+        .collect()
+}
+
+#[derive(Clone)]
+pub(crate) struct Identity(native_tls::Identity);
+
+impl Identity {
+    pub(crate) fn from_pkcs12(der: &[u8], password: &str) -> Result<Identity, Error> {
+        Ok(Identity(native_tls::Identity::from_pkcs12(der, password)?))
+    }
+
+    pub(crate) fn from_pem(certificates: &[u8], key: &[u8]) -> Result<Identity, Error> {
+        Ok(Identity(native_tls::Identity::from_pkcs8(
+            certificates,
+            key,
+        )?))
+    }
+}
+
+fn to_protocol(version: TlsVersion) -> native_tls::Protocol {
+    match version {
+        TlsVersion::Tls10 => native_tls::Protocol::Tlsv10,
+        TlsVersion::Tls11 => native_tls::Protocol::Tlsv11,
+        TlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+        TlsVersion::Tls13 => native_tls::Protocol::Tlsv13,
+    }
+}
+
+/// Performs TLS handshakes using `native-tls`.
+#[derive(Clone)]
+pub(crate) struct TlsConnector(tokio_tls::TlsConnector);
+
+impl TlsConnector {
+    pub(crate) fn new(config: &TlsConfig) -> Result<TlsConnector, Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+
+        for certificate in &config.root_certificates {
+            builder.add_root_certificate(native_tls::Certificate::from_der(certificate.to_der())?);
+        }
+
+        if let Some(ref identity) = config.identity {
+            builder.identity((identity.0).0.clone());
+        }
+
+        if let Some(min_version) = config.min_version {
+            builder.min_protocol_version(Some(to_protocol(min_version)));
+        }
+
+        if let Some(max_version) = config.max_version {
+            builder.max_protocol_version(Some(to_protocol(max_version)));
+        }
+
+        if config.alpn_http2 {
+            builder.request_alpns(&["h2", "http/1.1"]);
+        }
+
+        builder
+            .disable_built_in_roots(config.disable_built_in_roots)
+            .danger_accept_invalid_certs(config.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(config.accept_invalid_hostnames);
+
+        Ok(TlsConnector(tokio_tls::TlsConnector::from(
+            builder.build()?,
+        )))
+    }
+
+    /// Returns true if HTTP/2 was negotiated with ALPN on `stream`.
+    pub(crate) fn negotiated_http2<S>(stream: &TlsStream<S>) -> bool
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        match stream.get_ref().negotiated_alpn() {
+            Ok(Some(protocol)) => protocol == b"h2",
+            _ => false,
+        }
+    }
+
+    /// Performs the TLS handshake with `host` over `stream`.
+    pub(crate) fn connect<S>(
+        &self,
+        host: &str,
+        stream: S,
+    ) -> Box<dyn Future<Item = TlsStream<S>, Error = Box<dyn StdError + Send + Sync>> + Send>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Box::new(
+            self.0
+                .connect(host, stream)
+                .map_err(|error| Box::new(error) as Box<dyn StdError + Send + Sync>),
+        )
+    }
+}
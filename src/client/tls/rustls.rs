@@ -0,0 +1,248 @@
+use super::{TlsConfig, TlsVersion};
+use failure::{self, Error};
+use futures::{future, Future};
+use rustls::{self, internal::pemfile, Session};
+use std::error::Error as StdError;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls;
+use webpki;
+use webpki_roots;
+
+/// A TLS encrypted stream, provided by `rustls`.
+pub type TlsStream<S> = tokio_rustls::client::TlsStream<S>;
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+pub(crate) fn check_der_certificate(der: &[u8]) -> Result<(), Error> {
+    webpki::trust_anchor_util::cert_der_as_trust_anchor(der)?;
+    Ok(())
+}
+
+pub(crate) fn parse_pem_certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    pemfile::certs(&mut &pem[..])
+        .map(|certificates| {
+            certificates
+                .into_iter()
+                .map(|certificate| certificate.0)
+                .collect()
+        })
+        .map_err(|()| failure::err_msg("invalid PEM encoded certificate"))
+}
+
+#[derive(Clone)]
+pub(crate) struct Identity {
+    certificates: Vec<rustls::Certificate>,
+    key: rustls::PrivateKey,
+}
+
+impl Identity {
+    pub(crate) fn from_pkcs12(_der: &[u8], _password: &str) -> Result<Identity, Error> {
+        Err(failure::err_msg(
+            "PKCS #12 identities are not supported by the rustls backend",
+        ))
+    }
+
+    pub(crate) fn from_pem(certificates: &[u8], key: &[u8]) -> Result<Identity, Error> {
+        let certificates = pemfile::certs(&mut &certificates[..])
+            .map_err(|()| failure::err_msg("invalid PEM encoded certificate"))?;
+        if certificates.is_empty() {
+            return Err(failure::err_msg("no certificate found in PEM"));
+        }
+
+        let key = pemfile::pkcs8_private_keys(&mut &key[..])
+            .map_err(|()| failure::err_msg("invalid PEM encoded private key"))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| failure::err_msg("no PKCS #8 private key found in PEM"))?;
+        rustls::sign::any_supported_type(&key)
+            .map_err(|()| failure::err_msg("unsupported private key type"))?;
+
+        Ok(Identity { certificates, key })
+    }
+}
+
+fn to_protocol(version: TlsVersion) -> Option<rustls::ProtocolVersion> {
+    match version {
+        TlsVersion::Tls12 => Some(rustls::ProtocolVersion::TLSv1_2),
+        TlsVersion::Tls13 => Some(rustls::ProtocolVersion::TLSv1_3),
+        TlsVersion::Tls10 | TlsVersion::Tls11 => None,
+    }
+}
+
+/// Performs TLS handshakes using `rustls`.
+#[derive(Clone)]
+pub(crate) struct TlsConnector(Arc<rustls::ClientConfig>);
+
+impl TlsConnector {
+    pub(crate) fn new(config: &TlsConfig) -> Result<TlsConnector, Error> {
+        let mut client_config = rustls::ClientConfig::new();
+
+        if !config.disable_built_in_roots {
+            client_config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        }
+
+        for certificate in &config.root_certificates {
+            client_config
+                .root_store
+                .add(&rustls::Certificate(certificate.to_der().to_vec()))?;
+        }
+
+        if let Some(ref identity) = config.identity {
+            let identity = &identity.0;
+            client_config
+                .set_single_client_cert(identity.certificates.clone(), identity.key.clone());
+        }
+
+        // rustls only implements TLS 1.2 and 1.3.
+        let min_version = config.min_version.unwrap_or(TlsVersion::Tls12);
+        let max_version = config.max_version.unwrap_or(TlsVersion::Tls13);
+        client_config.versions = [TlsVersion::Tls13, TlsVersion::Tls12]
+            .iter()
+            .filter(|version| min_version <= **version && **version <= max_version)
+            .filter_map(|version| to_protocol(*version))
+            .collect();
+        if client_config.versions.is_empty() {
+            return Err(failure::err_msg(
+                "the rustls backend only supports TLS 1.2 and TLS 1.3",
+            ));
+        }
+
+        if config.alpn_http2 {
+            client_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        }
+
+        if config.accept_invalid_certs {
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoCertificateVerification));
+        } else if config.accept_invalid_hostnames {
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoHostnameVerification));
+        }
+
+        Ok(TlsConnector(Arc::new(client_config)))
+    }
+
+    /// Returns true if HTTP/2 was negotiated with ALPN on `stream`.
+    pub(crate) fn negotiated_http2<S>(stream: &TlsStream<S>) -> bool {
+        stream.get_ref().1.get_alpn_protocol() == Some(&b"h2"[..])
+    }
+
+    /// Performs the TLS handshake with `host` over `stream`.
+    pub(crate) fn connect<S>(
+        &self,
+        host: &str,
+        stream: S,
+    ) -> Box<dyn Future<Item = TlsStream<S>, Error = Box<dyn StdError + Send + Sync>> + Send>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        // webpki only validates certificates for DNS names.
+        if host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok()
+        {
+            return Box::new(future::err(
+                format!(
+                    "the rustls backend doesn't support IP address hosts, \
+                     connect to {} by its DNS name or use native-tls",
+                    host
+                )
+                .into(),
+            ));
+        }
+
+        let dns_name = match webpki::DNSNameRef::try_from_ascii_str(host) {
+            Ok(dns_name) => dns_name,
+            Err(_) => {
+                return Box::new(future::err(
+                    format!("invalid DNS name for TLS: {}", host).into(),
+                ))
+            }
+        };
+
+        Box::new(
+            tokio_rustls::TlsConnector::from(self.0.clone())
+                .connect(dns_name, stream)
+                .map_err(|error| error.into()),
+        )
+    }
+}
+
+/// Accepts any server certificate.
+struct NoCertificateVerification;
+
+impl rustls::ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+/// Verifies the server certificate chain without checking
+/// that the certificate is valid for the hostname.
+struct NoHostnameVerification;
+
+impl rustls::ServerCertVerifier for NoHostnameVerification {
+    fn verify_server_cert(
+        &self,
+        roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        let (end_entity, intermediates) = presented_certs
+            .split_first()
+            .ok_or(rustls::TLSError::NoCertificatesPresented)?;
+
+        let end_entity =
+            webpki::EndEntityCert::from(&end_entity.0).map_err(rustls::TLSError::WebPKIError)?;
+        let intermediates: Vec<&[u8]> = intermediates
+            .iter()
+            .map(|certificate| certificate.0.as_ref())
+            .collect();
+        let trust_anchors: Vec<webpki::TrustAnchor> = roots
+            .roots
+            .iter()
+            .map(|root| root.to_trust_anchor())
+            .collect();
+        let now = webpki::Time::try_from(SystemTime::now())
+            .map_err(|_| rustls::TLSError::FailedToGetCurrentTime)?;
+
+        end_entity
+            .verify_is_valid_tls_server_cert(
+                SUPPORTED_SIG_ALGS,
+                &webpki::TLSServerTrustAnchors(&trust_anchors),
+                &intermediates,
+                now,
+            )
+            .map_err(rustls::TLSError::WebPKIError)?;
+
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,201 @@
+//! Tracing of requests, enabled by the `tracing` feature.
+//!
+//! Each request gets a `request` span, with its method, url and body size
+//! when known, under which the client emits:
+//!
+//! - a `DEBUG` event for every request sent, including retries, redirects
+//!   and authentication challenges, with its url and the headers sent,
+//!   cookies and proxy credentials included,
+//! - an `INFO` event when the response is received, with its status, the
+//!   duration of the request and the number of retries and redirects,
+//! - a `DEBUG` event when the response body has been read, with the number
+//!   of bytes received, before decompression,
+//! - a `WARN` event when the request or the body read fails.
+//!
+//! The values of sensitive headers are masked, see
+//! `HttperClientBuilder::sensitive_header`, as well as url passwords and
+//! the credential, security token and signature of urls presigned with
+//! AWS SigV4.
+
+use client::redact::{redact_uri, SensitiveHeaders};
+use client::redirect::{RedirectChain, SendRequest};
+use client::response::Response;
+use failure::Error;
+use futures::{Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::{self, Chunk};
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{field, Span};
+
+/// Traces a request, from the first attempt to its response body.
+#[derive(Clone)]
+pub(crate) struct RequestTrace {
+    span: Span,
+    start: Instant,
+    attempts: Arc<AtomicUsize>,
+    // The response body bytes received, counted before decoding.
+    response_bytes: Arc<AtomicU64>,
+    sensitive_headers: Arc<SensitiveHeaders>,
+}
+
+impl RequestTrace {
+    pub(crate) fn new(
+        request: &hyper::Request<hyper::Body>,
+        sensitive_headers: &Arc<SensitiveHeaders>,
+    ) -> RequestTrace {
+        let span = info_span!(
+            "request",
+            method = %request.method(),
+            url = %redact_uri(request.uri()),
+            request_bytes = field::Empty,
+        );
+        if let Some(length) = request.body().content_length() {
+            span.record("request_bytes", length);
+        }
+
+        RequestTrace {
+            span,
+            start: Instant::now(),
+            attempts: Arc::new(AtomicUsize::new(0)),
+            response_bytes: Arc::new(AtomicU64::new(0)),
+            sensitive_headers: sensitive_headers.clone(),
+        }
+    }
+
+    /// Logs `request`, just before it is sent.
+    pub(crate) fn sending(&self, request: &hyper::Request<hyper::Body>) {
+        debug!(
+            parent: &self.span,
+            method = %request.method(),
+            url = %redact_uri(request.uri()),
+            headers = ?self.sensitive_headers.redact(request.headers()),
+            "sending request"
+        );
+    }
+
+    /// Counts the bytes of the body of `response` as they are received.
+    pub(crate) fn count_body(
+        &self,
+        response: hyper::Response<hyper::Body>,
+    ) -> hyper::Response<hyper::Body> {
+        let bytes = self.response_bytes.clone();
+        response.map(|body| {
+            hyper::Body::wrap_stream(body.map(move |chunk| {
+                bytes.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+                chunk
+            }))
+        })
+    }
+
+    /// Counts the attempts made with `send`, the first one and the retries.
+    pub(crate) fn attempts(&self, send: SendRequest) -> SendRequest {
+        let attempts = self.attempts.clone();
+        Arc::new(move |request| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            send(request)
+        })
+    }
+
+    /// Polls `response` in the span of the request and logs its outcome.
+    pub(crate) fn instrument<F>(
+        self,
+        response: F,
+    ) -> impl Future<Item = Response, Error = Error> + Send
+    where
+        F: Future<Item = Response, Error = Error> + Send,
+    {
+        let response = Instrumented {
+            inner: response,
+            span: self.span.clone(),
+        };
+        response.then(move |result| {
+            let retries = self.attempts.load(Ordering::SeqCst).saturating_sub(1);
+            let duration_ms = self.start.elapsed().as_millis() as u64;
+
+            match result {
+                Ok(response) => {
+                    let redirects = response
+                        .extensions()
+                        .get::<RedirectChain>()
+                        .map_or(0, |chain| chain.uris().len().saturating_sub(1));
+                    info!(
+                        parent: &self.span,
+                        status = response.status().as_u16(),
+                        duration_ms,
+                        retries,
+                        redirects,
+                        "response received"
+                    );
+                    Ok(response.map_body(|body| {
+                        hyper::Body::wrap_stream(TracedBody { body, trace: self })
+                    }))
+                }
+                Err(error) => {
+                    warn!(
+                        parent: &self.span,
+                        error = %error,
+                        duration_ms,
+                        retries,
+                        "request failed"
+                    );
+                    Err(error)
+                }
+            }
+        })
+    }
+}
+
+/// A future polled in a span.
+struct Instrumented<F> {
+    inner: F,
+    span: Span,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let _entered = self.span.enter();
+        self.inner.poll()
+    }
+}
+
+/// A response body logging the bytes received once it is read.
+struct TracedBody {
+    body: hyper::Body,
+    trace: RequestTrace,
+}
+
+impl Stream for TracedBody {
+    type Item = Chunk;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.body.poll() {
+            Ok(Async::Ready(Some(chunk))) => Ok(Async::Ready(Some(chunk))),
+            Ok(Async::Ready(None)) => {
+                debug!(
+                    parent: &self.trace.span,
+                    response_bytes = self.trace.response_bytes.load(Ordering::SeqCst),
+                    duration_ms = self.trace.start.elapsed().as_millis() as u64,
+                    "response body read"
+                );
+                Ok(Async::Ready(None))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(error) => {
+                warn!(
+                    parent: &self.trace.span,
+                    error = %error,
+                    response_bytes = self.trace.response_bytes.load(Ordering::SeqCst),
+                    "response body failed"
+                );
+                Err(Box::new(error))
+            }
+        }
+    }
+}